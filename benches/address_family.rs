@@ -0,0 +1,45 @@
+//! Compares `Context::address_family` against setting each amount and computing the address
+//! from scratch. Run with `cargo bench --bench address_family --features test-util`.
+
+use std::time::Instant;
+
+use bitcoin::{Amount, Network};
+use ctvlib::{test_payout, ContextBuilder};
+
+const OUTPUTS: u8 = 50;
+const AMOUNTS: u64 = 2_000;
+
+fn main() {
+    let ctx = ContextBuilder::new(Network::Regtest)
+        .outputs((0..OUTPUTS).map(|n| test_payout(n, 10_000)))
+        .build()
+        .unwrap();
+    let amounts: Vec<_> = (1..=AMOUNTS).map(Amount::from_sat).collect();
+    let varying_output = 0;
+
+    let start = Instant::now();
+    let naive: Vec<_> = amounts
+        .iter()
+        .map(|amount| {
+            let mut ctx = ctx.clone();
+            ctx.fields.outputs[varying_output]
+                .set_amount(*amount)
+                .unwrap();
+            ctx.address().unwrap()
+        })
+        .collect();
+    let naive_time = start.elapsed();
+
+    let start = Instant::now();
+    let family = ctx.address_family(varying_output, &amounts).unwrap();
+    let family_time = start.elapsed();
+
+    assert_eq!(naive, family);
+    println!("{AMOUNTS} addresses over {OUTPUTS} outputs");
+    println!("set_amount + address: {naive_time:?}");
+    println!("address_family:       {family_time:?}");
+    println!(
+        "speedup:              {:.1}x",
+        naive_time.as_secs_f64() / family_time.as_secs_f64()
+    );
+}
@@ -0,0 +1,59 @@
+//! Times building and hashing a 10,000-leaf tree with the SHA256 backend selected by features.
+//! Compare the two backends by running it under each:
+//!
+//! ```text
+//! cargo bench --bench sha256_backend
+//! cargo bench --bench sha256_backend --no-default-features
+//! ```
+
+use std::time::Instant;
+
+use bitcoin::{hashes::Hash, Address, Amount, FeeRate, Network, ScriptBuf, Txid};
+use ctvlib::{tree_for_utxo, TxType};
+
+const LEAVES: u32 = 10_000;
+const BRANCHING: usize = 4;
+
+fn main() {
+    let payouts: Vec<_> = (0..LEAVES)
+        .map(|n| {
+            let script = ScriptBuf::from_bytes(n.to_le_bytes().to_vec());
+            (Address::p2wsh(&script, Network::Regtest), 1)
+        })
+        .collect();
+    let fee_rate = FeeRate::from_sat_per_vb(1).unwrap();
+
+    let start = Instant::now();
+    let tree = tree_for_utxo(
+        Amount::from_btc(10.0).unwrap(),
+        payouts,
+        fee_rate,
+        BRANCHING,
+        Network::Regtest,
+        TxType::Segwit,
+    )
+    .unwrap();
+    let build_time = start.elapsed();
+
+    let start = Instant::now();
+    let hashes = tree.all_template_hashes().unwrap();
+    let hash_time = start.elapsed();
+
+    let start = Instant::now();
+    let txs = tree.spending_tx(Txid::all_zeros(), 0).unwrap();
+    let spend_time = start.elapsed();
+
+    assert_eq!(hashes.len(), txs.len());
+    let backend = if cfg!(feature = "sha2") {
+        "sha2"
+    } else {
+        "bitcoin_hashes"
+    };
+    println!(
+        "{LEAVES} leaves, {} transactions, {backend} backend",
+        txs.len()
+    );
+    println!("tree_for_utxo:       {build_time:?}");
+    println!("all_template_hashes: {hash_time:?}");
+    println!("spending_tx:         {spend_time:?}");
+}
@@ -0,0 +1,269 @@
+use bitcoin::{absolute::LockTime, transaction::Version, Amount, Network, Sequence};
+
+use crate::{util::nums_point, Context, Error, Fields, Output, TxType};
+
+/// Builder for a `Context`, with sensible defaults for everything but the outputs.
+///
+/// By default, this builds a version 2 segwit transaction with no locktime and a single input
+/// with a non-final sequence that does not signal RBF.
+#[derive(Debug, Clone)]
+pub struct ContextBuilder {
+    network: Network,
+    tx_type: TxType,
+    nums_label: Option<String>,
+    version: Version,
+    locktime: LockTime,
+    sequences: Vec<Sequence>,
+    outputs: Vec<Output>,
+    input_idx: u32,
+    fee_output_index: Option<usize>,
+    ctv_flags: u8,
+    bip69_sort: bool,
+    anchor: Option<Amount>,
+}
+
+impl ContextBuilder {
+    pub fn new(network: Network) -> Self {
+        ContextBuilder {
+            network,
+            tx_type: TxType::default(),
+            nums_label: None,
+            version: Version::TWO,
+            locktime: LockTime::ZERO,
+            sequences: vec![Sequence::ENABLE_LOCKTIME_NO_RBF],
+            outputs: Vec::new(),
+            input_idx: 0,
+            fee_output_index: None,
+            ctv_flags: 0,
+            bip69_sort: false,
+            anchor: None,
+        }
+    }
+
+    pub fn tx_type(mut self, tx_type: TxType) -> Self {
+        self.tx_type = tx_type;
+        self
+    }
+
+    /// Use taproot with an unspendable internal key derived from `label` with
+    /// `util::nums_point`. The label is kept so `Context::keypath_unspendability_proof` can
+    /// prove the key path is unspendable.
+    pub fn nums_taproot(mut self, label: &str) -> Self {
+        self.tx_type = TxType::Taproot {
+            internal_key: nums_point(label),
+        };
+        self.nums_label = Some(label.into());
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn locktime(mut self, locktime: LockTime) -> Self {
+        self.locktime = locktime;
+        self
+    }
+
+    pub fn sequences(mut self, sequences: Vec<Sequence>) -> Self {
+        self.sequences = sequences;
+        self
+    }
+
+    /// Add a single output.
+    pub fn output(mut self, output: Output) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Add several outputs.
+    pub fn outputs(mut self, outputs: impl IntoIterator<Item = Output>) -> Self {
+        self.outputs.extend(outputs);
+        self
+    }
+
+    pub fn input_idx(mut self, input_idx: u32) -> Self {
+        self.input_idx = input_idx;
+        self
+    }
+
+    pub fn fee_output_index(mut self, fee_output_index: usize) -> Self {
+        self.fee_output_index = Some(fee_output_index);
+        self
+    }
+
+    /// **Experimental.** Commit to a nonzero flags byte. See `Fields::ctv_flags`.
+    pub fn ctv_flags(mut self, ctv_flags: u8) -> Self {
+        self.ctv_flags = ctv_flags;
+        self
+    }
+
+    /// Sort the outputs by amount, then `script_pubkey`, as in BIP-69.
+    ///
+    /// Output order is committed to by the template hash, so this must be decided when the
+    /// template is created. Once the CTV is funded, the order can no longer be changed.
+    pub fn bip69_sort(mut self, bip69_sort: bool) -> Self {
+        self.bip69_sort = bip69_sort;
+        self
+    }
+
+    /// Commit to a version 3 (TRUC) transaction with a keyless pay-to-anchor output appended
+    /// after all others, so that the transaction can pay zero fee and be bumped by a CPFP
+    /// child spending the anchor. With `bip69_sort`, the anchor is sorted along with the other
+    /// outputs instead.
+    ///
+    /// The anchor is a committed output like any other, so `anchor_value` is included in
+    /// `required_funding()` and must be funded.
+    pub fn zero_fee_with_anchor(mut self, anchor_value: Amount) -> Self {
+        self.anchor = Some(anchor_value);
+        self.version = Version(3);
+        self
+    }
+
+    /// Build the `Context`. A `fee_output_index` which is not the index of one of the outputs
+    /// is an error.
+    pub fn build(self) -> Result<Context, Error> {
+        let mut outputs = self.outputs;
+        let mut fee_output_index = self.fee_output_index;
+        if let Some(idx) = fee_output_index.filter(|&idx| idx >= outputs.len()) {
+            return Err(Error::InvalidOutputIndex(idx));
+        }
+        if let Some(anchor_value) = self.anchor {
+            outputs.push(Output::anchor(anchor_value, self.network)?);
+        }
+        if self.bip69_sort {
+            let mut keyed = outputs
+                .into_iter()
+                .enumerate()
+                .map(|(idx, output)| {
+                    let txout = output.as_txout(self.network)?;
+                    Ok(((txout.value, txout.script_pubkey), idx, output))
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            keyed.sort_by(|(a, _, _), (b, _, _)| a.cmp(b));
+            fee_output_index = fee_output_index
+                .and_then(|fee_idx| keyed.iter().position(|(_, idx, _)| *idx == fee_idx));
+            outputs = keyed.into_iter().map(|(_, _, output)| output).collect();
+        }
+        Ok(Context {
+            network: self.network,
+            tx_type: self.tx_type,
+            nums_label: self.nums_label,
+            fields: Fields {
+                version: self.version,
+                locktime: self.locktime,
+                sequences: self.sequences,
+                outputs,
+                input_idx: self.input_idx,
+                fee_output_index,
+                scriptsigs: Vec::new(),
+                ctv_flags: self.ctv_flags,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ctv::anchor_script, test_util::test_payout};
+
+    use super::*;
+
+    #[test]
+    fn test_zero_fee_with_anchor() {
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .output(Output::Data {
+                data: "hello".into(),
+                label: None,
+            })
+            .zero_fee_with_anchor(Amount::from_sat(240))
+            .build()
+            .unwrap();
+        assert_eq!(ctx.fields.version, Version(3));
+        assert_eq!(ctx.required_funding().unwrap(), Amount::from_sat(240));
+        let anchor = ctx.fields.outputs[1].as_txout(Network::Regtest).unwrap();
+        assert_eq!(anchor.script_pubkey, anchor_script());
+    }
+
+    #[test]
+    fn test_bip69_sort() {
+        let outputs = [
+            test_payout(1, 3_000),
+            test_payout(2, 1_000),
+            test_payout(3, 2_000),
+        ];
+        let (lower, higher) = {
+            let script = |output: &Output| output.as_txout(Network::Regtest).unwrap().script_pubkey;
+            let (a, b) = (test_payout(4, 1_000), test_payout(5, 1_000));
+            if script(&a) < script(&b) {
+                (a, b)
+            } else {
+                (b, a)
+            }
+        };
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .outputs(outputs.clone())
+            .output(higher.clone())
+            .output(lower.clone())
+            .fee_output_index(0)
+            .bip69_sort(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            ctx.fields.outputs,
+            [
+                outputs[1].clone(),
+                lower,
+                higher,
+                outputs[2].clone(),
+                outputs[0].clone(),
+            ]
+        );
+        assert_eq!(ctx.fields.fee_output_index, Some(4));
+        assert_eq!(ctx.fee_output(), Some(4));
+
+        let unsorted = ContextBuilder::new(Network::Regtest)
+            .outputs(outputs.clone())
+            .fee_output_index(0)
+            .build()
+            .unwrap();
+        assert_eq!(unsorted.fields.outputs, outputs);
+        assert_eq!(unsorted.fields.fee_output_index, Some(0));
+    }
+
+    #[test]
+    fn test_anchor_is_sorted_with_bip69() {
+        let payment = test_payout(1, 10_000);
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .output(payment.clone())
+            .zero_fee_with_anchor(Amount::from_sat(240))
+            .bip69_sort(true)
+            .build()
+            .unwrap();
+        let anchor = Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap();
+        assert_eq!(ctx.fields.outputs, [anchor, payment]);
+    }
+
+    #[test]
+    fn test_fee_output_index_must_be_in_range() {
+        let data = Output::Data {
+            data: "fee".into(),
+            label: None,
+        };
+        for bip69_sort in [false, true] {
+            let built = ContextBuilder::new(Network::Regtest)
+                .output(data.clone())
+                .fee_output_index(1)
+                .bip69_sort(bip69_sort)
+                .build();
+            assert!(matches!(built, Err(Error::InvalidOutputIndex(1))));
+        }
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .output(data)
+            .fee_output_index(0)
+            .build()
+            .unwrap();
+        assert_eq!(ctx.fee_output(), None);
+    }
+}
@@ -0,0 +1,121 @@
+use bitcoin::{consensus::encode::serialize_hex, Amount, OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::{Context, Error};
+
+/// A self-contained export of a CTV tree: the template, the outpoint funding it, and every
+/// transaction needed to unroll it. See `Context::export_bundle`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bundle {
+    pub template: Context,
+
+    /// The outpoint funding the root of the tree.
+    pub funding: OutPoint,
+
+    /// The spending transactions, in the order they may be broadcast.
+    pub transactions: Vec<BundleTransaction>,
+}
+
+/// One spending transaction of a `Bundle`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleTransaction {
+    pub txid: Txid,
+
+    /// The consensus-encoded transaction, as hex.
+    pub hex: String,
+
+    /// The outpoint this transaction spends, which must be confirmed first.
+    pub spends: OutPoint,
+
+    /// The total value of the outputs of this transaction.
+    pub amount: Amount,
+
+    /// Whether this is a leaf of the tree, which no further transactions depend on.
+    pub terminal: bool,
+}
+
+impl Context {
+    /// Export this template and its full spend plan from the given funding outpoint as a JSON
+    /// `Bundle`, which may be handed to another service and loaded with `Context::import_bundle`.
+    pub fn export_bundle(&self, funding_txid: Txid, funding_vout: u32) -> Result<String, Error> {
+        Ok(serde_json::to_string(&self.bundle(OutPoint {
+            txid: funding_txid,
+            vout: funding_vout,
+        })?)?)
+    }
+
+    /// Load a JSON `Bundle`, checking that its transactions are exactly those the template
+    /// generates from the funding outpoint.
+    pub fn import_bundle(json: &str) -> Result<Bundle, Error> {
+        let bundle: Bundle = serde_json::from_str(json)?;
+        if bundle.template.bundle(bundle.funding)? != bundle {
+            return Err(Error::TemplateMismatch);
+        }
+        Ok(bundle)
+    }
+
+    fn bundle(&self, funding: OutPoint) -> Result<Bundle, Error> {
+        let transactions = self
+            .spend_plan(funding.txid, funding.vout)?
+            .into_iter()
+            .map(|step| BundleTransaction {
+                txid: step.txid,
+                hex: serialize_hex(&step.transaction),
+                spends: step.spends,
+                amount: step
+                    .transaction
+                    .output
+                    .iter()
+                    .map(|txout| txout.value)
+                    .sum(),
+                terminal: step.terminal,
+            })
+            .collect();
+        Ok(Bundle {
+            template: self.clone(),
+            funding,
+            transactions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, Network};
+
+    use crate::{ContextBuilder, Output};
+
+    use super::*;
+
+    #[test]
+    fn test_bundle_roundtrip() {
+        let leaf = ContextBuilder::new(Network::Regtest)
+            .output(Output::Data {
+                data: "leaf".into(),
+                label: None,
+            })
+            .output(Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        let root = ContextBuilder::new(Network::Regtest)
+            .output(Output::Tree {
+                tree: Box::new(leaf),
+                amount: Amount::from_sat(1_000),
+                label: None,
+            })
+            .build()
+            .unwrap();
+        let json = root.export_bundle(Txid::all_zeros(), 1).unwrap();
+        let bundle = Context::import_bundle(&json).unwrap();
+        assert_eq!(bundle.template, root);
+        assert_eq!(bundle.transactions.len(), 2);
+        assert_eq!(bundle.transactions[0].amount, Amount::from_sat(1_000));
+        assert!(bundle.transactions[1].terminal);
+
+        let tampered = json.replace(&bundle.transactions[1].hex, "00");
+        assert!(matches!(
+            Context::import_bundle(&tampered),
+            Err(Error::TemplateMismatch)
+        ));
+    }
+}
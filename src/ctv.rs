@@ -1,48 +1,181 @@
 use bitcoin::{
     absolute::LockTime,
     address::{NetworkChecked, NetworkUnchecked},
-    opcodes::all::OP_NOP4,
-    script::PushBytesBuf,
-    taproot::{LeafVersion, TaprootBuilder, TaprootSpendInfo},
+    hashes::{hex::DisplayHex, Hash},
+    key::TapTweak,
+    locktime::relative,
+    opcodes::all::{OP_NOP4, OP_PUSHBYTES_32},
+    script::{Instruction, PushBytesBuf},
+    sighash::{Prevouts, SighashCache, TapSighashType},
+    taproot::{
+        self, ControlBlock, LeafVersion, TaprootBuilder, TaprootSpendInfo, TAPROOT_ANNEX_PREFIX,
+    },
     transaction::Version,
-    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
-    Witness, XOnlyPublicKey,
+    Address, Amount, FeeRate, Network, OutPoint, PublicKey, Script, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid, Weight, Witness, XOnlyPublicKey,
 };
 
-use secp256k1::SECP256K1;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use secp256k1::{Keypair, Message, SECP256K1};
 use serde::{Deserialize, Serialize};
 
-use crate::{Error, TemplateHash};
+use crate::{
+    tmplhash::{template_hash_family, template_hash_with_flags, template_tx, util::sha256},
+    util::nums_point,
+    ContextBuilder, Error,
+};
 
 /// The main interface type for working with CTV.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Context {
+    /// The network addresses are generated for. Every `bitcoin::Network` variant is supported.
+    /// `Testnet4` is not available in the version of `bitcoin` this crate depends on.
     pub network: Network,
 
     /// Dictates whether CTV lock will a P2WSH or P2TR spend.
     pub tx_type: TxType,
 
+    /// The label the taproot internal key was derived from with `util::nums_point`, if any.
+    /// See `Context::keypath_unspendability_proof`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nums_label: Option<String>,
+
     /// The fields that a CTV hash commits to.
     pub fields: Fields,
 }
 
 impl Context {
+    /// Package reusable `fields` for a particular network and transaction type. Any addresses in
+    /// the outputs must be valid for `network`.
+    pub fn from_fields(fields: Fields, network: Network, tx_type: TxType) -> Context {
+        Context {
+            network,
+            tx_type,
+            nums_label: None,
+            fields,
+        }
+    }
+
+    /// The committed fields alone, without the network or transaction type.
+    pub fn into_fields(self) -> Fields {
+        self.fields
+    }
+
+    /// A `Context` paying `amount` to a single `destination`, which must be valid for `network`.
+    /// Everything else takes the `ContextBuilder` defaults, including a single non-final
+    /// sequence.
+    pub fn passthrough(
+        destination: Address<NetworkUnchecked>,
+        amount: Amount,
+        network: Network,
+        tx_type: TxType,
+    ) -> Result<Context, Error> {
+        let destination = destination.require_network(network)?;
+        ContextBuilder::new(network)
+            .tx_type(tx_type)
+            .output(Output::sweep(destination.as_unchecked().clone(), amount))
+            .build()
+    }
+
+    /// Build a `Context` committing to exactly the given `txouts`, by mapping each one back to
+    /// the `Output` variant which produces it. See `Output::from_txout`.
+    pub fn from_txouts(
+        txouts: Vec<TxOut>,
+        network: Network,
+        tx_type: TxType,
+        sequences: Vec<Sequence>,
+        version: Version,
+        locktime: LockTime,
+        input_idx: u32,
+    ) -> Result<Context, Error> {
+        let outputs = txouts
+            .iter()
+            .map(|txout| Output::from_txout(txout, network))
+            .collect::<Result<_, _>>()?;
+        Ok(Context {
+            network,
+            tx_type,
+            nums_label: None,
+            fields: Fields {
+                version,
+                locktime,
+                sequences,
+                outputs,
+                input_idx,
+                fee_output_index: None,
+                scriptsigs: Vec::new(),
+                ctv_flags: 0,
+            },
+        })
+    }
+
     pub fn locking_script(&self) -> Result<ScriptBuf, Error> {
-        let tmplhash = self.ctv()?;
-        let mut pbf = PushBytesBuf::new();
-        pbf.extend_from_slice(&tmplhash)?;
-        Ok(bitcoin::script::Builder::new()
-            .push_slice(pbf)
-            .push_opcode(OP_NOP4)
-            .into_script())
+        ctv_script(&self.ctv()?)
+    }
+
+    /// The locking script as hex, e.g. `20<hash>b3`, as accepted by `bitcoin-cli decodescript`.
+    pub fn locking_script_hex(&self) -> Result<String, Error> {
+        Ok(self.locking_script()?.to_hex_string())
+    }
+
+    /// The locking script as human-readable assembly, e.g. `OP_PUSHBYTES_32 <hash> OP_NOP4`.
+    pub fn locking_script_asm(&self) -> Result<String, Error> {
+        Ok(self.locking_script()?.to_asm_string())
+    }
+
+    /// Check that the locking script pushes the template hash with `OP_PUSHBYTES_32`, the
+    /// canonical minimal push. A script using `OP_PUSHDATA1` instead would be non-standard, and
+    /// would not match a locking script reconstructed from the hash.
+    pub fn verify_minimal_push(&self) -> Result<(), Error> {
+        check_minimal_push(&self.locking_script()?)
     }
 
     pub fn address(&self) -> Result<Address<NetworkChecked>, Error> {
-        let locking_script = self.locking_script()?;
+        self.address_for_script(self.locking_script()?)
+    }
+
+    /// Whether `other` locks funds to the same `script_pubkey` as this CTV. Contexts differing
+    /// only in labels or other uncommitted metadata fund the same address; compare `ctv()` as well
+    /// to tell whether the commitment itself changed.
+    pub fn same_address_as(&self, other: &Context) -> Result<bool, Error> {
+        Ok(self.address()?.script_pubkey() == other.address()?.script_pubkey())
+    }
+
+    /// The addresses this CTV would have with the amount of output `varying_output` set to each
+    /// of `amounts` in turn. Only the outputs hash is recomputed for each amount, and only the
+    /// outputs after `varying_output` are re-encoded, so this is much faster than calling
+    /// `set_amount` and `address` for each one.
+    pub fn address_family(
+        &self,
+        varying_output: usize,
+        amounts: &[Amount],
+    ) -> Result<Vec<Address<NetworkChecked>>, Error> {
+        match self.fields.outputs.get(varying_output) {
+            None => return Err(Error::InvalidOutputIndex(varying_output)),
+            Some(Output::Data { .. }) => return Err(Error::CannotSetAmountOnData),
+            Some(_) => {}
+        }
+        template_hash_family(
+            &self.as_tx()?,
+            self.fields.input_idx,
+            self.fields.ctv_flags,
+            varying_output,
+            amounts,
+        )?
+        .into_iter()
+        .map(|hash| self.address_for_script(ctv_script(&hash)?))
+        .collect()
+    }
+
+    fn address_for_script(
+        &self,
+        locking_script: ScriptBuf,
+    ) -> Result<Address<NetworkChecked>, Error> {
         match self.tx_type {
             TxType::Segwit => Ok(Address::p2wsh(&locking_script, self.network)),
-            TxType::Taproot { internal_key } => {
-                let tsi = self.taproot_spend_info(internal_key)?;
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                let tsi = leaf_spend_info(locking_script, internal_key)?;
                 Ok(Address::p2tr(
                     SECP256K1,
                     internal_key,
@@ -53,165 +186,2610 @@ impl Context {
         }
     }
 
+    /// The tweaked taproot output key, i.e. the witness program of `address()`. This is an
+    /// error for segwit contexts.
+    pub fn taproot_output_key(&self) -> Result<XOnlyPublicKey, Error> {
+        match self.tx_type {
+            TxType::Segwit => Err(Error::NotTaproot),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                Ok(self
+                    .taproot_spend_info(internal_key)?
+                    .output_key()
+                    .to_inner())
+            }
+        }
+    }
+
     /// Generate a spending transaction (or series of them) to spend the outputs of the CTV.
     /// In the event that this represents a CTV tree, it will generate a series of transactions
     /// that may be spent in order.
     ///
     /// If this does not have any `Output::Tree` outputs, then it will generate a single
     /// transaction to spend to all of the outputs.
+    ///
+    /// Every transaction must have an output carrying value, or a pay-to-anchor output, as one
+    /// with only `OP_RETURN` outputs is non-standard. Otherwise this is
+    /// `Error::NoSpendableOutputs`.
+    ///
+    /// Templates committing to more than one sequence must be spent with `spending_tx_multi`.
     pub fn spending_tx(&self, txid: Txid, vout: u32) -> Result<Vec<Transaction>, Error> {
-        let mut transactions = Vec::new();
-        let tx = Transaction {
-            version: self.fields.version,
-            lock_time: self.fields.locktime,
-            input: vec![TxIn {
-                previous_output: OutPoint { txid, vout },
-                script_sig: Default::default(),
-                sequence: *self
-                    .fields
-                    .sequences
-                    .first()
-                    .ok_or(Error::MissingSequence)?,
-                witness: self.witness()?,
-            }],
-            output: self.txouts()?,
-        };
-        let current_txid = tx.txid();
-        transactions.push(tx);
-        if let Some(Output::Tree { tree, amount: _ }) = self.fields.outputs.first() {
-            transactions.extend_from_slice(&tree.spending_tx(current_txid, 0)?);
-        }
-        Ok(transactions)
+        Ok(self
+            .spend_plan(txid, vout)?
+            .into_iter()
+            .map(|step| step.transaction)
+            .collect())
     }
 
-    /// The actual hash that this CTV represents. May be used in locking scripts.
-    pub fn ctv(&self) -> Result<Vec<u8>, Error> {
-        self.as_tx()?.template_hash(self.fields.input_idx)
+    /// Same as `spending_tx`, but each transaction is paired with its txid and the outpoint it
+    /// spends. Every transaction comes after the one it depends on, so the steps may be
+    /// broadcast in order.
+    pub fn spend_plan(&self, txid: Txid, vout: u32) -> Result<Vec<SpendStep>, Error> {
+        let mut plan = Vec::new();
+        self.collect_spend_plan(OutPoint { txid, vout }, usize::MAX, &mut plan, &mut |_| {})?;
+        Ok(plan)
     }
 
-    fn taproot_spend_info(&self, internal_key: XOnlyPublicKey) -> Result<TaprootSpendInfo, Error> {
-        TaprootBuilder::new()
-            .add_leaf(0, self.locking_script()?)?
-            .finalize(SECP256K1, internal_key)
-            .map_err(|_| Error::UnknownError("Taproot not finalizable".into()))
+    /// Same as `spending_tx`, but calls `progress` with the number of transactions built so far
+    /// and the total number in the tree after each one, e.g. to report progress when unrolling
+    /// a large tree.
+    pub fn spending_tx_with_progress(
+        &self,
+        txid: Txid,
+        vout: u32,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<Transaction>, Error> {
+        let total = self.nodes().len();
+        let mut plan = Vec::new();
+        self.collect_spend_plan(
+            OutPoint { txid, vout },
+            usize::MAX,
+            &mut plan,
+            &mut |built| progress(built, total),
+        )?;
+        Ok(plan.into_iter().map(|step| step.transaction).collect())
     }
 
-    fn as_tx(&self) -> Result<Transaction, Error> {
-        let input = self
-            .fields
-            .sequences
+    /// Same as `spending_tx`, but only unrolls the tree down to `max_depth`. A `max_depth` of
+    /// zero generates only the transaction spending this context. The contexts below it may be
+    /// unrolled later from their own outpoints.
+    pub fn spending_tx_to_depth(
+        &self,
+        txid: Txid,
+        vout: u32,
+        max_depth: usize,
+    ) -> Result<Vec<Transaction>, Error> {
+        let mut plan = Vec::new();
+        self.collect_spend_plan(OutPoint { txid, vout }, max_depth, &mut plan, &mut |_| {})?;
+        Ok(plan.into_iter().map(|step| step.transaction).collect())
+    }
+
+    /// Same as `spending_tx`, for a template committing to more than one input. `inputs` holds
+    /// the outpoint spent by each input, in order, and must have one entry per committed
+    /// sequence. The CTV itself is spent by the input at `input_idx`; the other inputs are left
+    /// unsigned for the caller to complete.
+    pub fn spending_tx_multi(&self, inputs: &[OutPoint]) -> Result<Vec<Transaction>, Error> {
+        let spends = *inputs
+            .get(self.fields.input_idx as usize)
+            .ok_or_else(|| self.input_count_mismatch(inputs.len()))?;
+        let mut plan = Vec::new();
+        self.push_spend_plan(
+            self.build_spend(inputs)?,
+            spends,
+            usize::MAX,
+            &mut plan,
+            &mut |_| {},
+        )?;
+        Ok(plan.into_iter().map(|step| step.transaction).collect())
+    }
+
+    /// The transaction spending this CTV together with the other committed inputs, consolidating
+    /// every UTXO in `utxos` into the committed outputs. Each UTXO becomes the input at its
+    /// position, so there must be one per committed sequence, and together they must hold at
+    /// least `required_funding()`. The UTXO at `input_idx` must pay to this CTV's address.
+    ///
+    /// As in `spending_tx_multi`, only the CTV input at `input_idx` is given a witness; the
+    /// other inputs are left unsigned for the caller to complete. Nested trees are not unrolled.
+    pub fn spend_consolidating(&self, utxos: Vec<(OutPoint, TxOut)>) -> Result<Transaction, Error> {
+        if utxos.len() != self.fields.sequences.len() {
+            return Err(self.input_count_mismatch(utxos.len()));
+        }
+        let funding = utxos
             .iter()
-            .map(|seq| TxIn {
-                sequence: *seq,
-                ..Default::default()
+            .try_fold(Amount::ZERO, |total, (_, txout)| {
+                total.checked_add(txout.value)
             })
-            .collect();
-        let output: Result<Vec<TxOut>, Error> = self
-            .fields
-            .outputs
+            .ok_or(Error::AmountOverflow)?;
+        let required = self.required_funding()?;
+        if funding < required {
+            return Err(Error::Underfunded { funding, required });
+        }
+        let input_idx = self.fields.input_idx as usize;
+        let (_, ctv_utxo) = utxos
+            .get(input_idx)
+            .ok_or(Error::InvalidInputIndex(input_idx))?;
+        if ctv_utxo.script_pubkey != self.address()?.script_pubkey() {
+            return Err(Error::NotCtvUtxo(input_idx));
+        }
+        let inputs: Vec<_> = utxos.into_iter().map(|(outpoint, _)| outpoint).collect();
+        self.build_spend(&inputs)
+    }
+
+    fn collect_spend_plan(
+        &self,
+        spends: OutPoint,
+        max_depth: usize,
+        plan: &mut Vec<SpendStep>,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<(), Error> {
+        self.push_spend_plan(self.spend_tx(spends)?, spends, max_depth, plan, progress)
+    }
+
+    fn push_spend_plan(
+        &self,
+        transaction: Transaction,
+        spends: OutPoint,
+        max_depth: usize,
+        plan: &mut Vec<SpendStep>,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<(), Error> {
+        // An OP_RETURN-only spend is non-standard, so it could never confirm.
+        let spendable = transaction
+            .output
             .iter()
-            .map(|output| output.as_txout(self.network))
-            .collect();
+            .any(|txout| txout.value > Amount::ZERO || txout.script_pubkey == anchor_script());
+        if !spendable {
+            return Err(Error::NoSpendableOutputs);
+        }
+        let txid = transaction.txid();
+        plan.push(SpendStep {
+            transaction,
+            txid,
+            spends,
+            terminal: self.is_terminal(),
+        });
+        progress(plan.len());
+        if max_depth == 0 {
+            return Ok(());
+        }
+        for (vout, output) in self.fields.outputs.iter().enumerate() {
+            if let Output::Tree { tree, .. } = output {
+                let vout = vout as u32;
+                let spends = OutPoint { txid, vout };
+                tree.collect_spend_plan(spends, max_depth - 1, plan, progress)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The weight of the transaction spending this CTV, which does not depend on which outpoints
+    /// are spent. For multi-input templates, only the CTV input is counted as signed.
+    pub fn spend_weight(&self) -> Result<Weight, Error> {
+        Ok(self.dummy_spending_tx()?.weight())
+    }
+
+    /// The smallest UTXO which can both fund this CTV and pay to spend it at `fee_rate`:
+    /// `required_funding()` plus `spend_fee()`. This is an error if any output in the tree is
+    /// below the dust limit, as the spend would not be relayed at any funding value.
+    pub fn minimum_funding(&self, fee_rate: FeeRate) -> Result<Amount, Error> {
+        self.check_dust()?;
+        self.required_funding()?
+            .checked_add(self.spend_fee(fee_rate)?)
+            .ok_or(Error::AmountOverflow)
+    }
+
+    /// Check that no output in the tree is below the dust limit.
+    pub(crate) fn check_dust(&self) -> Result<(), Error> {
+        for (path, node) in self.nodes() {
+            for (index, output) in node.fields.outputs.iter().enumerate() {
+                if let Output::Data { .. } = output {
+                    continue;
+                }
+                let txout = output.as_txout(node.network)?;
+                if txout.value < txout.script_pubkey.dust_value() {
+                    return Err(Error::DustOutput {
+                        path,
+                        index,
+                        amount: txout.value,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The transaction spending this CTV, with its inputs spending a zeroed txid and vout.
+    /// This has the real witness and the same weight as any actual spend, so it is suitable for
+    /// size estimation, but it cannot be broadcast.
+    pub fn dummy_spending_tx(&self) -> Result<Transaction, Error> {
+        let inputs = vec![OutPoint::new(Txid::all_zeros(), 0); self.fields.sequences.len().max(1)];
+        self.build_spend(&inputs)
+    }
+
+    /// The exact fee for the transaction spending this CTV at `fee_rate`, computed from its
+    /// weight rather than a rounded vsize.
+    pub fn spend_fee(&self, fee_rate: FeeRate) -> Result<Amount, Error> {
+        fee_rate
+            .fee_wu(self.spend_weight()?)
+            .ok_or(Error::AmountOverflow)
+    }
+
+    /// The single-input spend of this CTV. Only the first committed sequence would make it into
+    /// the transaction, and the CTV must be spent by input 0, so any other template is rejected
+    /// rather than producing a spend whose template hash does not match.
+    fn spend_tx(&self, spends: OutPoint) -> Result<Transaction, Error> {
+        if self.fields.sequences.len() > 1 {
+            return Err(self.input_count_mismatch(1));
+        }
+        if self.fields.input_idx != 0 {
+            return Err(Error::InputCountMismatch(format!(
+                "template is spent by input {} but a single-input spend only has input 0; use \
+                 spending_tx_multi to spend a template with more than one input",
+                self.fields.input_idx
+            )));
+        }
+        self.build_spend(&[spends])
+    }
+
+    fn build_spend(&self, inputs: &[OutPoint]) -> Result<Transaction, Error> {
+        if self.fields.sequences.is_empty() {
+            return Err(Error::MissingSequence);
+        }
+        if inputs.len() != self.fields.sequences.len() {
+            return Err(self.input_count_mismatch(inputs.len()));
+        }
+        let input_idx = self.fields.input_idx as usize;
+        let input = inputs
+            .iter()
+            .zip(&self.fields.sequences)
+            .enumerate()
+            .map(|(idx, (previous_output, sequence))| {
+                let (script_sig, witness) = if idx == input_idx {
+                    (self.script_sig()?, self.witness()?)
+                } else {
+                    let script_sig = self.fields.scriptsigs.get(idx).cloned();
+                    (script_sig.unwrap_or_default(), Witness::new())
+                };
+                Ok(TxIn {
+                    previous_output: *previous_output,
+                    script_sig,
+                    sequence: *sequence,
+                    witness,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
         Ok(Transaction {
             version: self.fields.version,
             lock_time: self.fields.locktime,
             input,
-            output: output?,
+            output: self.txouts()?,
         })
     }
 
-    fn txouts(&self) -> Result<Vec<TxOut>, Error> {
-        self.fields
-            .outputs
+    fn input_count_mismatch(&self, found: usize) -> Error {
+        Error::InputCountMismatch(format!(
+            "template commits to {} inputs but the spend has {found}; use spending_tx_multi \
+             to spend a template with more than one input",
+            self.fields.sequences.len()
+        ))
+    }
+
+    /// Generate an unsigned transaction which funds this CTV at output 0 with exactly
+    /// `required_funding()`, plus an optional change output.
+    pub fn funding_tx(
+        &self,
+        inputs: Vec<TxIn>,
+        change: Option<TxOut>,
+    ) -> Result<Transaction, Error> {
+        let mut output = vec![TxOut {
+            value: self.required_funding()?,
+            script_pubkey: self.address()?.script_pubkey(),
+        }];
+        output.extend(change);
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: inputs,
+            output,
+        })
+    }
+
+    /// Same as `funding_tx`, but also commits to the template hash in an `OP_RETURN` at output 1,
+    /// so that the funding transaction is publicly linked to the covenant it enables.
+    pub fn funding_tx_with_commitment(
+        &self,
+        inputs: Vec<TxIn>,
+        change: Option<TxOut>,
+    ) -> Result<Transaction, Error> {
+        let mut tx = self.funding_tx(inputs, change)?;
+        let mut pb = PushBytesBuf::new();
+        pb.extend_from_slice(&self.ctv()?)?;
+        tx.output.insert(
+            1,
+            TxOut {
+                value: Amount::ZERO,
+                script_pubkey: ScriptBuf::new_op_return(&pb),
+            },
+        );
+        Ok(tx)
+    }
+
+    /// Find the output of `funding_tx` which funds this CTV, i.e. pays to its address with at
+    /// least `required_funding()`. Returns the vout and value of the first such output.
+    pub fn find_funding_output(&self, funding_tx: &Transaction) -> Result<(u32, Amount), Error> {
+        let script_pubkey = self.address()?.script_pubkey();
+        let required = self.required_funding()?;
+        funding_tx
+            .output
             .iter()
-            .map(|output| output.as_txout(self.network))
-            .collect()
+            .enumerate()
+            .find(|(_, txout)| txout.script_pubkey == script_pubkey && txout.value >= required)
+            .map(|(vout, txout)| (vout as u32, txout.value))
+            .ok_or(Error::FundingNotFound)
     }
 
-    fn witness(&self) -> Result<Witness, Error> {
-        let mut witness = Witness::new();
-        let script = self.locking_script()?;
-        witness.push(script.clone());
+    /// For taproot contexts, check that the internal key is one of the `known_nums` points (for
+    /// example, from `util::nums_point`), so that the key path cannot bypass the covenant.
+    /// Segwit contexts have no key path and always pass.
+    pub fn assert_unspendable_keypath(&self, known_nums: &[XOnlyPublicKey]) -> Result<(), Error> {
         match self.tx_type {
-            TxType::Segwit => {}
-            TxType::Taproot { internal_key } => {
-                let tsi = self.taproot_spend_info(internal_key)?;
-                let cb = tsi
-                    .control_block(&(script, LeafVersion::TapScript))
-                    .ok_or_else(|| Error::UnknownError("Taproot construction error".into()))?;
-                witness.push(cb.serialize());
+            TxType::Segwit => Ok(()),
+            TxType::Taproot { internal_key } if known_nums.contains(&internal_key) => Ok(()),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                Err(Error::SpendableKeypath(internal_key))
             }
         }
-        Ok(witness)
     }
-}
 
-/// The fields to which a CTV hash commits.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Fields {
-    pub version: Version,
-    pub locktime: LockTime,
-    pub sequences: Vec<Sequence>,
-    pub outputs: Vec<Output>,
-    pub input_idx: u32,
-}
+    /// Find how `tx` relates to this tree: whether it funds any of the tree's addresses, and
+    /// whether it is the committed spend of any context in the tree.
+    pub fn match_transaction(&self, tx: &Transaction) -> Result<Vec<TreeMatch>, Error> {
+        let mut matches = Vec::new();
+        for (path, node) in self.nodes() {
+            let script_pubkey = node.address()?.script_pubkey();
+            for (vout, txout) in tx.output.iter().enumerate() {
+                if txout.script_pubkey == script_pubkey {
+                    matches.push(TreeMatch::Funded {
+                        path: path.clone(),
+                        vout: vout as u32,
+                        amount: txout.value,
+                    });
+                }
+            }
+            if node.verify_spend(tx)? {
+                matches.push(TreeMatch::Spent { path });
+            }
+        }
+        Ok(matches)
+    }
 
-/// Outputs committed to by a `Ctv`.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(untagged)]
-pub enum Output {
-    /// Spend a specific amount to a specific address.
-    Address {
-        address: Address<NetworkUnchecked>,
-        amount: Amount,
-    },
+    /// If the taproot internal key was derived with `util::nums_point` from `nums_label`, a proof
+    /// which lets a counterparty recompute the key and confirm that only the CTV script path can
+    /// spend this output.
+    pub fn keypath_unspendability_proof(&self) -> Option<UnspendabilityProof> {
+        let TxType::Taproot { internal_key } = self.tx_type else {
+            return None;
+        };
+        let proof = UnspendabilityProof {
+            label: self.nums_label.clone()?,
+            internal_key,
+        };
+        proof.verify().then_some(proof)
+    }
 
-    /// Commit to an `OP_RETURN` output.
-    Data { data: String },
+    /// An unspendable taproot internal key tied to this covenant: the `util::nums_point` of the
+    /// hex-encoded template hash. Anyone holding the template can recompute it, and it is
+    /// proven unspendable by an `UnspendabilityProof` with the hex hash as its label.
+    ///
+    /// The template hash does not depend on the internal key, so using this key does not
+    /// change the hash it was derived from.
+    pub fn derived_nums_key(&self) -> Result<XOnlyPublicKey, Error> {
+        Ok(nums_point(&self.ctv()?.to_lower_hex_string()))
+    }
 
-    /// Commit an `amount` to a nested `Ctv` output. Use this to create a congestion control tree
-    /// or another type of covenant tree.
-    Tree { tree: Box<Context>, amount: Amount },
-}
+    /// Check whether `tx` satisfies this CTV, i.e. whether its template hash at `input_idx`
+    /// matches the one committed to by the locking script.
+    pub fn verify_spend(&self, tx: &Transaction) -> Result<bool, Error> {
+        let expected = hash_array(self.ctv()?)?;
+        let actual = hash_array(template_hash_with_flags(
+            tx,
+            self.fields.input_idx,
+            self.fields.ctv_flags,
+        )?)?;
+        Ok(hashes_equal(&expected, &actual))
+    }
 
-impl Output {
-    /// Turn this output into a `TxOut` that may be used in a bitcoin `Transaction` struct.
-    pub fn as_txout(&self, network: Network) -> Result<TxOut, Error> {
-        Ok(match self {
-            Output::Address { address, amount } => TxOut {
-                value: *amount,
-                script_pubkey: address.clone().require_network(network)?.script_pubkey(),
-            },
-            Output::Data { data } => {
-                let mut pb = PushBytesBuf::new();
-                pb.extend_from_slice(data.as_bytes())?;
-                TxOut {
-                    value: Amount::ZERO,
-                    script_pubkey: ScriptBuf::new_op_return(&pb),
+    /// Spend this CTV cooperatively through the key path, which requires the internal key of a
+    /// `TxType::TaprootWithKeypath` context. The transaction pays the committed outputs, but
+    /// does not reveal the CTV script.
+    ///
+    /// `prevouts` are the outputs spent by each input of the transaction, which for this
+    /// single-input spend is the CTV UTXO itself.
+    pub fn cooperative_close(
+        &self,
+        keypair: &Keypair,
+        prevouts: &[TxOut],
+        txid: Txid,
+        vout: u32,
+    ) -> Result<Transaction, Error> {
+        let TxType::TaprootWithKeypath { internal_key } = self.tx_type else {
+            return Err(Error::NoKeypath);
+        };
+        if keypair.x_only_public_key().0 != internal_key {
+            return Err(Error::KeypairMismatch);
+        }
+        let mut tx = self.spend_tx(OutPoint { txid, vout })?;
+        let sighash = SighashCache::new(&tx).taproot_key_spend_signature_hash(
+            0,
+            &Prevouts::All(prevouts),
+            TapSighashType::Default,
+        )?;
+        let merkle_root = self.taproot_spend_info(internal_key)?.merkle_root();
+        let tweaked = keypair.tap_tweak(SECP256K1, merkle_root).to_inner();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let signature = taproot::Signature {
+            sig: SECP256K1.sign_schnorr_no_aux_rand(&msg, &tweaked),
+            hash_ty: TapSighashType::Default,
+        };
+        tx.input[0].witness = Witness::from_slice(&[signature.to_vec()]);
+        Ok(tx)
+    }
+
+    /// Build the spends for this tree against a funding output of `funding_value`, and check that
+    /// every one is valid modulo CTV semantics: each spends no more than its input holds,
+    /// satisfies its template hash, and carries a witness which commits to its prevout's
+    /// `script_pubkey` (the P2WSH script hash, or the P2TR control block).
+    ///
+    /// With the `verify` feature, each CTV input is also run through libbitcoinconsensus script
+    /// verification. It treats `OP_NOP4` as a NOP, so this checks that the witness and P2WSH
+    /// structure is consensus-valid, but not the CTV itself. The bundled libconsensus predates
+    /// taproot, so P2TR spends pass this step unchecked.
+    pub fn verify_generated_spend(&self, funding_value: Amount) -> Result<(), Error> {
+        let funding = TxOut {
+            value: funding_value,
+            script_pubkey: self.address()?.script_pubkey(),
+        };
+        let plan = self.spend_plan(Txid::all_zeros(), 0)?;
+        let mut prevouts = HashMap::new();
+        for (step, (_, node)) in plan.iter().zip(self.nodes()) {
+            let prevout = match prevouts.get(&step.spends) {
+                Some(prevout) => prevout,
+                None => &funding,
+            };
+            let spent = node.required_funding()?;
+            if spent > prevout.value {
+                return Err(Error::Underfunded {
+                    funding: prevout.value,
+                    required: spent,
+                });
+            }
+            if !node.verify_spend(&step.transaction)? {
+                return Err(Error::TemplateMismatch);
+            }
+            node.check_witness_commitment(
+                &step.transaction.input[0].witness,
+                &prevout.script_pubkey,
+            )?;
+            #[cfg(feature = "verify")]
+            node.verify_consensus(&step.transaction, prevout)?;
+            for (vout, txout) in step.transaction.output.iter().enumerate() {
+                prevouts.insert(OutPoint::new(step.txid, vout as u32), txout.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Run libbitcoinconsensus script verification on the CTV input of `tx`, which spends
+    /// `prevout`.
+    #[cfg(feature = "verify")]
+    fn verify_consensus(&self, tx: &Transaction, prevout: &TxOut) -> Result<(), Error> {
+        let tx = bitcoin::consensus::serialize(tx);
+        let input_idx = self.fields.input_idx as usize;
+        Ok(prevout
+            .script_pubkey
+            .verify(input_idx, prevout.value, &tx)?)
+    }
+
+    /// Check that the witness generated for every context in the tree spends the address
+    /// generated for it: the witness script or leaf must be exactly the locking script, and for
+    /// taproot the control block must prove it is committed to by the output key.
+    ///
+    /// The address and witness each rebuild the locking script, so this catches them diverging.
+    pub fn assert_address_witness_consistency(&self) -> Result<(), Error> {
+        for (_, node) in self.nodes() {
+            let script_pubkey = node.address()?.script_pubkey();
+            node.check_witness_commitment(&node.witness()?, &script_pubkey)?;
+        }
+        Ok(())
+    }
+
+    fn check_witness_commitment(
+        &self,
+        witness: &Witness,
+        script_pubkey: &Script,
+    ) -> Result<(), Error> {
+        let script = self.locking_script()?;
+        let committed = match self.tx_type {
+            TxType::Segwit => {
+                witness.last() == Some(script.as_bytes())
+                    && ScriptBuf::new_p2wsh(&script.wscript_hash()) == *script_pubkey
+            }
+            TxType::Taproot { .. } | TxType::TaprootWithKeypath { .. } => {
+                let output_key = script_pubkey
+                    .is_p2tr()
+                    .then(|| XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..]).ok())
+                    .flatten();
+                let script_path = tapscript_and_control_block(witness);
+                let leaf = script_path.map(|(leaf, _)| leaf);
+                let control_block = script_path.and_then(|(_, cb)| ControlBlock::decode(cb).ok());
+                match (output_key, control_block) {
+                    (Some(output_key), Some(control_block)) => {
+                        leaf == Some(script.as_bytes())
+                            && control_block
+                                .verify_taproot_commitment(SECP256K1, output_key, &script)
+                    }
+                    _ => false,
                 }
             }
-            Output::Tree { tree, amount } => TxOut {
-                value: *amount,
-                script_pubkey: tree.address()?.script_pubkey(),
-            },
-        })
+        };
+        if committed {
+            Ok(())
+        } else {
+            Err(Error::WitnessMismatch)
+        }
     }
 
-    /// Extract amount of final output.
-    pub fn amount(&self) -> Amount {
-        match self {
-            Output::Address { address: _, amount } => *amount,
-            Output::Data { data: _ } => Amount::ZERO,
-            Output::Tree { tree: _, amount } => *amount,
+    /// The `script_sig` for the input spending this CTV. This is always empty for the supported
+    /// native segwit and taproot spends.
+    ///
+    /// P2SH-wrapped spends are not supported: their `script_sig` pushes the redeem script, which
+    /// contains the template hash, which in turn commits to the `script_sig`.
+    pub fn script_sig(&self) -> Result<ScriptBuf, Error> {
+        match self.tx_type {
+            TxType::Segwit | TxType::Taproot { .. } | TxType::TaprootWithKeypath { .. } => {
+                Ok(ScriptBuf::new())
+            }
         }
     }
-}
 
-#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
-pub enum TxType {
-    #[default]
-    Segwit,
-    Taproot {
-        internal_key: XOnlyPublicKey,
-    },
+    /// Commit to `script` as the scriptSig of `input`, which the spend will then carry. Any
+    /// non-empty scriptSig makes the template hash commit to every input's scriptSig.
+    ///
+    /// This is for the other inputs of a multi-input template (see `spending_tx_multi`), such as
+    /// legacy P2SH inputs whose scriptSig is known in advance. The CTV input itself is a native
+    /// segwit or taproot spend, which must have an empty scriptSig.
+    pub fn commit_scriptsig(&mut self, input: usize, script: ScriptBuf) -> Result<(), Error> {
+        if input >= self.fields.sequences.len() {
+            return Err(Error::InvalidInputIndex(input));
+        }
+        if input == self.fields.input_idx as usize {
+            return Err(Error::ScriptSigOnCtvInput);
+        }
+        if self.fields.scriptsigs.len() <= input {
+            self.fields.scriptsigs.resize(input + 1, ScriptBuf::new());
+        }
+        self.fields.scriptsigs[input] = script;
+        Ok(())
+    }
+
+    /// A stable identifier for the whole context, including nested trees and labels, suitable
+    /// for use as a database key. This is the SHA256 of its JSON serialization, so unlike `ctv`,
+    /// changing a label changes the ID.
+    pub fn template_id(&self) -> [u8; 32] {
+        let json = serde_json::to_vec(self).expect("Context always serializes");
+        sha256(json).try_into().expect("SHA256 is always 32 bytes")
+    }
+
+    /// The actual hash that this CTV represents. May be used in locking scripts.
+    pub fn ctv(&self) -> Result<Vec<u8>, Error> {
+        template_hash_with_flags(&self.as_tx()?, self.fields.input_idx, self.fields.ctv_flags)
+    }
+
+    /// The template hash this CTV would have if its outputs were reordered so that output `i`
+    /// is the current output `order[i]`. `self` is not modified. `order` must be a permutation
+    /// of `0..outputs.len()`.
+    pub fn ctv_with_output_order(&self, order: &[usize]) -> Result<[u8; 32], Error> {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        if !sorted.iter().copied().eq(0..self.fields.outputs.len()) {
+            return Err(Error::InvalidOutputOrder(order.to_vec()));
+        }
+        let txouts = self.txouts()?;
+        let tx = self.template(order.iter().map(|&idx| txouts[idx].clone()).collect());
+        hash_array(template_hash_with_flags(
+            &tx,
+            self.fields.input_idx,
+            self.fields.ctv_flags,
+        )?)
+    }
+
+    /// The template hash this CTV would have if `fields.version` were `version`, e.g. to compare
+    /// the commitment before and after moving a template to version 3 (TRUC). `self` is not
+    /// modified.
+    pub fn hash_with_version(&self, version: Version) -> Result<[u8; 32], Error> {
+        let mut tx = self.as_tx()?;
+        tx.version = version;
+        hash_array(template_hash_with_flags(
+            &tx,
+            self.fields.input_idx,
+            self.fields.ctv_flags,
+        )?)
+    }
+
+    /// The total amount committed to by the outputs of this transaction. A UTXO funding this
+    /// `Context` must hold at least this much; anything above it is paid as a fee.
+    pub fn required_funding(&self) -> Result<Amount, Error> {
+        self.fields
+            .outputs
+            .iter()
+            .try_fold(Amount::ZERO, |total, output| {
+                total.checked_add(output.amount())
+            })
+            .ok_or(Error::AmountOverflow)
+    }
+
+    /// The fee paid by spending this CTV from a UTXO holding `funding_value`: everything not
+    /// committed to an output. An unexpectedly large fee means value is being lost.
+    pub fn fee_implied_by(&self, funding_value: Amount) -> Result<Amount, Error> {
+        let required = self.required_funding()?;
+        funding_value
+            .checked_sub(required)
+            .ok_or(Error::Underfunded {
+                funding: funding_value,
+                required,
+            })
+    }
+
+    /// Every `Output::Data` payload in the tree, paired with the index of the transaction which
+    /// carries it in the order returned by `spending_tx`.
+    pub fn data_payloads(&self) -> Vec<(usize, Vec<u8>)> {
+        self.nodes()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(tx_idx, (_, node))| {
+                node.fields
+                    .outputs
+                    .iter()
+                    .filter_map(move |output| match output {
+                        Output::Data { data, .. } => Some((tx_idx, data.as_bytes().to_vec())),
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+
+    /// Find addresses paid more than once anywhere in the tree, which leaks privacy. Each is paired
+    /// with its positions among all `Output::Address` outputs in the tree, counted in the order
+    /// returned by `spending_tx`.
+    pub fn find_reused_addresses(&self) -> Vec<(Address<NetworkUnchecked>, Vec<usize>)> {
+        let nodes = self.nodes();
+        let addresses = nodes
+            .iter()
+            .flat_map(|(_, node)| &node.fields.outputs)
+            .filter_map(|output| match output {
+                Output::Address { address, .. } => Some(address),
+                _ => None,
+            });
+        let mut positions: Vec<(&Address<NetworkUnchecked>, Vec<usize>)> = Vec::new();
+        let mut seen = HashMap::new();
+        for (position, address) in addresses.enumerate() {
+            let idx = *seen.entry(address).or_insert_with(|| {
+                positions.push((address, Vec::new()));
+                positions.len() - 1
+            });
+            positions[idx].1.push(position);
+        }
+        positions
+            .into_iter()
+            .filter(|(_, seen_at)| seen_at.len() > 1)
+            .map(|(address, seen_at)| (address.clone(), seen_at))
+            .collect()
+    }
+
+    /// The index of the output which fees should be deducted from when spending this transaction.
+    ///
+    /// Uses `fields.fee_output_index` if set, unless it is out of range or an `Output::Data`,
+    /// which cannot pay fees. Otherwise, defaults to the first `Output::Tree` so that fees
+    /// cascade down the tree, or else the last `Output::Address`.
+    pub fn fee_output(&self) -> Option<usize> {
+        let outputs = &self.fields.outputs;
+        self.fields
+            .fee_output_index
+            .filter(|&idx| {
+                outputs
+                    .get(idx)
+                    .is_some_and(|output| !matches!(output, Output::Data { .. }))
+            })
+            .or_else(|| {
+                outputs
+                    .iter()
+                    .position(|output| matches!(output, Output::Tree { .. }))
+            })
+            .or_else(|| {
+                outputs
+                    .iter()
+                    .rposition(|output| matches!(output, Output::Address { .. }))
+            })
+    }
+
+    /// The number of levels of nested `Output::Tree` contexts below this one. A `Context` with no
+    /// tree outputs has a depth of zero.
+    pub fn tree_depth(&self) -> usize {
+        self.nodes()
+            .iter()
+            .map(|(path, _)| path.len())
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// The total amount held in the UTXOs of every context at `depth` in the tree. The root is at
+    /// depth zero, and is taken to hold exactly `required_funding()`.
+    pub fn value_at_depth(&self, depth: usize) -> Result<Amount, Error> {
+        if depth > self.tree_depth() {
+            return Err(Error::InvalidDepth(depth));
+        }
+        if depth == 0 {
+            return self.required_funding();
+        }
+        self.nodes()
+            .into_iter()
+            .filter(|(path, _)| path.len() == depth - 1)
+            .flat_map(|(_, node)| &node.fields.outputs)
+            .filter(|output| matches!(output, Output::Tree { .. }))
+            .try_fold(Amount::ZERO, |total, output| {
+                total.checked_add(output.amount())
+            })
+            .ok_or(Error::AmountOverflow)
+    }
+
+    /// The worst-case number of blocks before every context in the tree can be spent, assuming
+    /// each transaction is broadcast as soon as its relative timelock allows. This is the largest
+    /// sum of committed relative block delays along any path from the root to a leaf.
+    ///
+    /// Only the sequence of the CTV input counts, and only from version 2, as in
+    /// `spendable_after`. Relative timelocks measured in time rather than blocks are an error.
+    pub fn max_unroll_delay(&self) -> Result<u32, Error> {
+        let sequence = self
+            .fields
+            .sequences
+            .get(self.fields.input_idx as usize)
+            .ok_or(Error::MissingSequence)?;
+        // Relative timelocks are only enforced from version 2.
+        let relative = Some(sequence)
+            .filter(|_| self.fields.version.0 >= 2)
+            .and_then(|seq| seq.to_relative_lock_time());
+        let delay = match relative {
+            Some(relative::LockTime::Blocks(height)) => height.value() as u32,
+            Some(relative::LockTime::Time(_)) => return Err(Error::TimeBasedTimelock(*sequence)),
+            None => 0,
+        };
+        let mut children = 0;
+        for output in &self.fields.outputs {
+            if let Output::Tree { tree, .. } = output {
+                children = children.max(tree.max_unroll_delay()?);
+            }
+        }
+        Ok(delay.saturating_add(children))
+    }
+
+    /// The earliest block height at which the committed transaction can confirm, if the CTV is
+    /// funded in the block at `funding_height`. This is the latest of the block after funding,
+    /// the block after the absolute `locktime` (when it is enforced), and the block at which the
+    /// relative timelock of the CTV input expires.
+    ///
+    /// Timelocks measured in time rather than blocks cannot be converted to a height, and are
+    /// ignored.
+    pub fn spendable_after(&self, funding_height: u32) -> u32 {
+        let mut height = funding_height.saturating_add(1);
+        let enforced = self
+            .fields
+            .sequences
+            .iter()
+            .any(|seq| *seq != Sequence::MAX);
+        if let (true, LockTime::Blocks(locktime)) = (enforced, self.fields.locktime) {
+            height = height.max(locktime.to_consensus_u32().saturating_add(1));
+        }
+        // Relative timelocks are only enforced from version 2.
+        let sequence = self.fields.sequences.get(self.fields.input_idx as usize);
+        let relative = sequence
+            .filter(|_| self.fields.version.0 >= 2)
+            .and_then(|seq| seq.to_relative_lock_time());
+        if let Some(relative::LockTime::Blocks(delay)) = relative {
+            height = height.max(funding_height.saturating_add(delay.value() as u32));
+        }
+        height
+    }
+
+    /// Whether this context is a leaf of the tree, i.e. commits to no `Output::Tree` outputs.
+    pub fn is_terminal(&self) -> bool {
+        !self
+            .fields
+            .outputs
+            .iter()
+            .any(|output| matches!(output, Output::Tree { .. }))
+    }
+
+    /// Whether this is a tree, a flat payment to addresses, a commitment to data alone, or has
+    /// no outputs at all.
+    pub fn kind(&self) -> ContextKind {
+        if self.fields.outputs.is_empty() {
+            ContextKind::Empty
+        } else if !self.is_terminal() {
+            ContextKind::Tree {
+                depth: self.tree_depth(),
+                leaves: self.leaf_count(),
+            }
+        } else if self
+            .fields
+            .outputs
+            .iter()
+            .all(|output| matches!(output, Output::Data { .. }))
+        {
+            ContextKind::DataCommitment
+        } else {
+            ContextKind::Payment
+        }
+    }
+
+    /// The number of contexts in the tree (including this one) which do not commit to any
+    /// further `Output::Tree` outputs.
+    pub fn leaf_count(&self) -> usize {
+        self.nodes()
+            .iter()
+            .filter(|(_, node)| node.is_terminal())
+            .count()
+    }
+
+    /// A copy of the nested context found by following `path`, a list of `Output::Tree` output
+    /// indices from this context. An empty path returns a copy of this context.
+    pub fn take_subtree(&self, path: &[usize]) -> Result<Context, Error> {
+        let mut node = self;
+        for &index in path {
+            node = match node.fields.outputs.get(index) {
+                Some(Output::Tree { tree, .. }) => tree,
+                Some(_) => return Err(Error::NotATree(index)),
+                None => return Err(Error::InvalidOutputIndex(index)),
+            };
+        }
+        Ok(node.clone())
+    }
+
+    /// A proof that the nested context at `path` (see `take_subtree`) is committed to by this
+    /// context's template hash: the template of every context along the path, root first. Each
+    /// template commits, through its `Output::Tree` output, to the `script_pubkey` of the next,
+    /// so a verifier holding only the root hash can check the whole path with
+    /// `verify_inclusion`.
+    pub fn inclusion_path(&self, path: &[usize]) -> Result<Vec<InclusionLevel>, Error> {
+        let mut levels = vec![self.inclusion_level()?];
+        let mut node = self;
+        for &index in path {
+            node = match node.fields.outputs.get(index) {
+                Some(Output::Tree { tree, .. }) => tree,
+                Some(_) => return Err(Error::NotATree(index)),
+                None => return Err(Error::InvalidOutputIndex(index)),
+            };
+            levels.push(node.inclusion_level()?);
+        }
+        Ok(levels)
+    }
+
+    fn inclusion_level(&self) -> Result<InclusionLevel, Error> {
+        Ok(InclusionLevel {
+            template: self.as_tx()?,
+            input_idx: self.fields.input_idx,
+            ctv_flags: self.fields.ctv_flags,
+            tx_type: self.tx_type,
+        })
+    }
+
+    /// The template hash of every context in the tree, depth-first from the root, in the same
+    /// order as the transactions returned by `spending_tx`.
+    pub fn all_template_hashes(&self) -> Result<Vec<[u8; 32]>, Error> {
+        self.nodes()
+            .into_iter()
+            .map(|(_, node)| hash_array(node.ctv()?))
+            .collect()
+    }
+
+    /// The address of every context in the tree, depth-first from the root, in the same order
+    /// as `all_template_hashes`.
+    pub fn tree_addresses(&self) -> Result<Vec<Address<NetworkChecked>>, Error> {
+        self.nodes()
+            .into_iter()
+            .map(|(_, node)| node.address())
+            .collect()
+    }
+
+    /// The `script_pubkey` of every context in the tree, for checking many scripts against the
+    /// tree without recomputing its addresses each time. See `owns_script`.
+    pub fn tree_scripts(&self) -> Result<HashSet<ScriptBuf>, Error> {
+        self.nodes()
+            .into_iter()
+            .map(|(_, node)| Ok(node.address()?.script_pubkey()))
+            .collect()
+    }
+
+    /// Whether `script` is the `script_pubkey` of any context in the tree, i.e. whether a UTXO
+    /// locked to it is a node of this tree. This builds `tree_scripts` on every call, so to
+    /// check many scripts, build it once and look each up in that instead.
+    pub fn owns_script(&self, script: &Script) -> bool {
+        self.tree_scripts()
+            .is_ok_and(|scripts| scripts.contains(script))
+    }
+
+    /// Replace the nested context at `path` (see `take_subtree`) with `subtree`, returning the
+    /// context it replaced. The amount of the `Output::Tree` holding it is left unchanged, so
+    /// `subtree` should fit within it.
+    pub fn replace_subtree(&mut self, path: &[usize], subtree: Context) -> Result<Context, Error> {
+        let mut node = self;
+        for &index in path {
+            node = match node.fields.outputs.get_mut(index) {
+                Some(Output::Tree { tree, .. }) => tree,
+                Some(_) => return Err(Error::NotATree(index)),
+                None => return Err(Error::InvalidOutputIndex(index)),
+            };
+        }
+        Ok(std::mem::replace(node, subtree))
+    }
+
+    /// Every context in the tree in breadth-first order, paired with its depth. All contexts at
+    /// depth zero (the root) come first, then all contexts at depth one, and so on.
+    pub fn iter_bfs(&self) -> impl Iterator<Item = (usize, &Context)> {
+        let mut queue = VecDeque::from([(0, self)]);
+        std::iter::from_fn(move || {
+            let (depth, node) = queue.pop_front()?;
+            for output in &node.fields.outputs {
+                if let Output::Tree { tree, .. } = output {
+                    queue.push_back((depth + 1, tree));
+                }
+            }
+            Some((depth, node))
+        })
+    }
+
+    /// Every context in the tree, depth-first, paired with the output-index path leading to it
+    /// from the root.
+    pub(crate) fn nodes(&self) -> Vec<(Vec<usize>, &Context)> {
+        let mut nodes = Vec::new();
+        self.collect_nodes(Vec::new(), &mut nodes);
+        nodes
+    }
+
+    fn collect_nodes<'a>(&'a self, path: Vec<usize>, nodes: &mut Vec<(Vec<usize>, &'a Context)>) {
+        nodes.push((path.clone(), self));
+        for (vout, output) in self.fields.outputs.iter().enumerate() {
+            if let Output::Tree { tree, .. } = output {
+                let mut path = path.clone();
+                path.push(vout);
+                tree.collect_nodes(path, nodes);
+            }
+        }
+    }
+
+    /// Move value between the `Output::Tree` outputs of this context, by setting each listed
+    /// output to its new amount. Each subtree is scaled proportionally to its new amount, keeping
+    /// the same fee. The reallocations must keep `required_funding()` unchanged, and nothing is
+    /// modified on error.
+    pub fn rebalance(&mut self, reallocations: &[(usize, Amount)]) -> Result<(), Error> {
+        let before = self.required_funding()?;
+        let mut ctx = self.clone();
+        for &(index, new_amount) in reallocations {
+            match ctx.fields.outputs.get_mut(index) {
+                Some(Output::Tree { tree, amount, .. }) => {
+                    rescale_tree(tree, amount, new_amount)?;
+                }
+                Some(_) => return Err(Error::NotATree(index)),
+                None => return Err(Error::InvalidOutputIndex(index)),
+            }
+        }
+        let after = ctx.required_funding()?;
+        if after != before {
+            return Err(Error::RebalanceMismatch { before, after });
+        }
+        *self = ctx;
+        Ok(())
+    }
+
+    /// A copy of this tree with every output scaled proportionally so that `required_funding()`
+    /// is `new_total`, e.g. because more funds were raised than the tree was designed for.
+    /// Nested trees keep their fees, and rounding remainders go to the last value-bearing output
+    /// of each context, so every context still sums exactly to the output funding it.
+    ///
+    /// This is an error if any output would end up below the dust limit.
+    pub fn rescale(&self, new_total: Amount) -> Result<Context, Error> {
+        let ctx = self.scaled(new_total)?;
+        ctx.check_dust()?;
+        Ok(ctx)
+    }
+
+    /// A copy of this context with every output scaled so that `required_funding()` is `total`.
+    /// Rounding remainders go to the last value-bearing output, and nested trees keep their fees.
+    pub(crate) fn scaled(&self, total: Amount) -> Result<Context, Error> {
+        let old_total = self.required_funding()?.to_sat() as u128;
+        if old_total == 0 {
+            return Err(Error::NothingToScale);
+        }
+        let mut amounts: Vec<Amount> = self
+            .fields
+            .outputs
+            .iter()
+            .map(|output| {
+                let sats = output.amount().to_sat() as u128 * total.to_sat() as u128 / old_total;
+                Amount::from_sat(sats as u64)
+            })
+            .collect();
+        let allocated = amounts.iter().copied().sum::<Amount>();
+        let last = self
+            .fields
+            .outputs
+            .iter()
+            .rposition(|output| !matches!(output, Output::Data { .. }));
+        if let Some(last) = last {
+            amounts[last] += total - allocated;
+        }
+
+        let mut ctx = self.clone();
+        for (output, new_amount) in ctx.fields.outputs.iter_mut().zip(amounts) {
+            match output {
+                Output::Address { amount, .. } => *amount = new_amount,
+                Output::Tree { tree, amount, .. } => rescale_tree(tree, amount, new_amount)?,
+                Output::Data { .. } => {}
+            }
+        }
+        Ok(ctx)
+    }
+
+    pub(crate) fn taproot_spend_info(
+        &self,
+        internal_key: XOnlyPublicKey,
+    ) -> Result<TaprootSpendInfo, Error> {
+        leaf_spend_info(self.locking_script()?, internal_key)
+    }
+
+    fn as_tx(&self) -> Result<Transaction, Error> {
+        Ok(self.template(self.txouts()?))
+    }
+
+    /// The transaction the template hash commits to, with the given outputs.
+    fn template(&self, outputs: Vec<TxOut>) -> Transaction {
+        let mut tx = template_tx(
+            self.fields.version,
+            self.fields.locktime,
+            &self.fields.sequences,
+            outputs,
+        );
+        for (txin, script_sig) in tx.input.iter_mut().zip(&self.fields.scriptsigs) {
+            txin.script_sig = script_sig.clone();
+        }
+        tx
+    }
+
+    fn txouts(&self) -> Result<Vec<TxOut>, Error> {
+        self.fields
+            .outputs
+            .iter()
+            .map(|output| output.as_txout(self.network))
+            .collect()
+    }
+
+    pub(crate) fn witness(&self) -> Result<Witness, Error> {
+        let mut witness = Witness::new();
+        let script = self.locking_script()?;
+        witness.push(script.clone());
+        match self.tx_type {
+            TxType::Segwit => {}
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                let tsi = self.taproot_spend_info(internal_key)?;
+                let cb = tsi
+                    .control_block(&(script, LeafVersion::TapScript))
+                    .ok_or_else(|| Error::UnknownError("Taproot construction error".into()))?;
+                witness.push(cb.serialize());
+            }
+        }
+        Ok(witness)
+    }
+
+    /// The taproot script path witness spending this CTV, with `annex` appended as the final
+    /// element. The annex must begin with `0x50`, which is prepended if missing.
+    ///
+    /// BIP-119 template hashes do not commit to the annex, so the spend still matches the
+    /// template whatever annex is attached. Segwit v0 has no annex, so this is an error for
+    /// segwit contexts.
+    ///
+    /// Bitcoin Core treats any annex as non-standard and will not relay a transaction carrying
+    /// one, so such a spend must be submitted directly to a miner.
+    pub fn witness_with_annex(&self, annex: &[u8]) -> Result<Witness, Error> {
+        if let TxType::Segwit = self.tx_type {
+            return Err(Error::NotTaproot);
+        }
+        let mut witness = self.witness()?;
+        if annex.first() == Some(&TAPROOT_ANNEX_PREFIX) {
+            witness.push(annex);
+        } else {
+            witness.push([&[TAPROOT_ANNEX_PREFIX], annex].concat());
+        }
+        Ok(witness)
+    }
+}
+
+/// A single transaction in the unrolling of a CTV tree. See `Context::spend_plan`.
+#[derive(Debug, Clone)]
+pub struct SpendStep {
+    pub transaction: Transaction,
+    pub txid: Txid,
+
+    /// The outpoint this transaction spends, which must be confirmed first.
+    pub spends: OutPoint,
+
+    /// Whether this is a leaf of the tree, which no further steps depend on.
+    pub terminal: bool,
+}
+
+/// One context along an inclusion proof. See `Context::inclusion_path`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionLevel {
+    /// The transaction the context's template hash commits to, with empty inputs.
+    pub template: Transaction,
+    pub input_idx: u32,
+    pub ctv_flags: u8,
+
+    /// How the context is locked, which determines the `script_pubkey` paying to it.
+    pub tx_type: TxType,
+}
+
+impl InclusionLevel {
+    /// The template hash of this level.
+    pub fn template_hash(&self) -> Result<[u8; 32], Error> {
+        hash_array(template_hash_with_flags(
+            &self.template,
+            self.input_idx,
+            self.ctv_flags,
+        )?)
+    }
+
+    /// The `script_pubkey` locking funds to this level's template hash.
+    fn script_pubkey(&self) -> Result<ScriptBuf, Error> {
+        let script = ctv_script(&self.template_hash()?)?;
+        match self.tx_type {
+            TxType::Segwit => Ok(ScriptBuf::new_p2wsh(&script.wscript_hash())),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                let tsi = leaf_spend_info(script, internal_key)?;
+                Ok(ScriptBuf::new_p2tr(
+                    SECP256K1,
+                    internal_key,
+                    tsi.merkle_root(),
+                ))
+            }
+        }
+    }
+}
+
+/// Check a proof from `Context::inclusion_path` that the context reached by following `path`
+/// from the context with template hash `root` is part of its tree. The first level must hash to
+/// `root`, and output `path[i]` of each level must pay to the `script_pubkey` of the next.
+pub fn verify_inclusion(
+    root: &[u8; 32],
+    path: &[usize],
+    proof: &[InclusionLevel],
+) -> Result<bool, Error> {
+    let Some(first) = proof.first() else {
+        return Ok(false);
+    };
+    if proof.len() != path.len() + 1 || !hashes_equal(&first.template_hash()?, root) {
+        return Ok(false);
+    }
+    for (&index, levels) in path.iter().zip(proof.windows(2)) {
+        let Some(txout) = levels[0].template.output.get(index) else {
+            return Ok(false);
+        };
+        if txout.script_pubkey != levels[1].script_pubkey()? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// A way in which a transaction relates to a CTV tree. See `Context::match_transaction`.
+///
+/// `path` is the list of output indices leading from the root context to the matched context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeMatch {
+    /// The transaction pays `amount` to the context's address at output `vout`.
+    Funded {
+        path: Vec<usize>,
+        vout: u32,
+        amount: Amount,
+    },
+
+    /// The transaction is the committed spend of the context.
+    Spent { path: Vec<usize> },
+}
+
+/// A coarse classification of a `Context`. See `Context::kind`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextKind {
+    /// A single transaction paying to one or more addresses.
+    Payment,
+
+    /// A tree of nested contexts, `depth` levels deep with `leaves` terminal contexts.
+    Tree { depth: usize, leaves: usize },
+
+    /// A transaction committing only to `OP_RETURN` data.
+    DataCommitment,
+
+    /// A transaction with no outputs, which is invalid.
+    Empty,
+}
+
+/// Set a tree output to `new_amount`, scaling the nested context to match while keeping its fee.
+fn rescale_tree(tree: &mut Context, amount: &mut Amount, new_amount: Amount) -> Result<(), Error> {
+    let required = tree.required_funding()?;
+    let fee = amount.checked_sub(required).ok_or(Error::Underfunded {
+        funding: *amount,
+        required,
+    })?;
+    let total = new_amount
+        .checked_sub(fee)
+        .ok_or(Error::InsufficientAmount {
+            amount: new_amount,
+            fee,
+        })?;
+    *tree = tree.scaled(total)?;
+    *amount = new_amount;
+    Ok(())
+}
+
+/// Evidence that a taproot internal key is a NUMS point with no known private key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnspendabilityProof {
+    /// The label passed to `util::nums_point`.
+    pub label: String,
+    pub internal_key: XOnlyPublicKey,
+}
+
+impl UnspendabilityProof {
+    /// Recompute the NUMS point from the label and check that it is the internal key.
+    pub fn verify(&self) -> bool {
+        nums_point(&self.label) == self.internal_key
+    }
+}
+
+/// The pay-to-anchor script, `OP_1 <0x4e73>`.
+pub(crate) fn anchor_script() -> ScriptBuf {
+    ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73])
+}
+
+/// Compare two template hashes in constant time, so timing does not leak the length of a partial
+/// match.
+pub fn hashes_equal(a: &[u8; 32], b: &[u8; 32]) -> bool {
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// The template hash committed to by the witness of a CTV spend. The witness script is the last
+/// element for segwit, and the leaf script before the control block for taproot, ignoring any
+/// annex. Returns `None` if there is no such script, or it is not a `<hash> OP_NOP4` CTV locking
+/// script.
+pub fn extract_ctv_from_witness(witness: &Witness, tx_type: TxType) -> Option<[u8; 32]> {
+    let script = match tx_type {
+        TxType::Segwit => witness.last(),
+        TxType::Taproot { .. } | TxType::TaprootWithKeypath { .. } => {
+            tapscript_and_control_block(witness).map(|(leaf, _)| leaf)
+        }
+    }?;
+    let mut instructions = Script::from_bytes(script).instructions();
+    match (
+        instructions.next(),
+        instructions.next(),
+        instructions.next(),
+    ) {
+        (Some(Ok(Instruction::PushBytes(hash))), Some(Ok(Instruction::Op(OP_NOP4))), None) => {
+            hash.as_bytes().try_into().ok()
+        }
+        _ => None,
+    }
+}
+
+/// The leaf script and control block of a taproot script path witness. As in BIP-341, a final
+/// element beginning with `0x50` is the annex, and is skipped.
+fn tapscript_and_control_block(witness: &Witness) -> Option<(&[u8], &[u8])> {
+    let mut elements = witness.iter().collect::<Vec<_>>();
+    if elements.len() >= 2 && elements.last()?.first() == Some(&TAPROOT_ANNEX_PREFIX) {
+        elements.pop();
+    }
+    let control_block = elements.pop()?;
+    let leaf = elements.pop()?;
+    Some((leaf, control_block))
+}
+
+/// A taproot output with one CTV leaf per template, so that any one of the committed transactions
+/// may be executed. Returns the address, and the control block for each template's leaf in the
+/// same order. Each template is spent through its leaf with `[locking_script, control_block]`.
+///
+/// Every template must be for the same network, which the address is generated for.
+pub fn multi_template_taproot(
+    templates: Vec<Context>,
+    internal_key: XOnlyPublicKey,
+) -> Result<(Address, Vec<ControlBlock>), Error> {
+    let network = templates.first().ok_or(Error::NoTemplates)?.network;
+    if let Some(other) = templates.iter().find(|ctx| ctx.network != network) {
+        return Err(Error::NetworkMismatch(network, other.network));
+    }
+    let scripts = templates
+        .iter()
+        .map(Context::locking_script)
+        .collect::<Result<Vec<_>, _>>()?;
+    let tsi = TaprootSpendInfo::with_huffman_tree(
+        SECP256K1,
+        internal_key,
+        scripts.iter().map(|script| (1, script.clone())),
+    )?;
+    let control_blocks = scripts
+        .into_iter()
+        .map(|script| {
+            tsi.control_block(&(script, LeafVersion::TapScript))
+                .ok_or_else(|| Error::UnknownError("Taproot construction error".into()))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((
+        Address::p2tr_tweaked(tsi.output_key(), network),
+        control_blocks,
+    ))
+}
+
+/// Reconstruct the `Context` which a confirmed `spend` of a CTV output must have committed to.
+/// The CTV input is the first whose witness carries a CTV script (see
+/// `extract_ctv_from_witness`), and the reconstructed template hash is checked against it.
+///
+/// Nested trees cannot be recovered from a single transaction, so every output becomes an
+/// `Output::Address` or `Output::Data`. The taproot internal key is taken from `tx_type`, as it
+/// does not affect the template hash.
+pub fn context_from_spend(
+    spend: &Transaction,
+    tx_type: TxType,
+    network: Network,
+) -> Result<Context, Error> {
+    let (input_idx, expected) = spend
+        .input
+        .iter()
+        .enumerate()
+        .find_map(|(idx, txin)| Some((idx, extract_ctv_from_witness(&txin.witness, tx_type)?)))
+        .ok_or(Error::NoCtvWitness)?;
+    let mut ctx = Context::from_txouts(
+        spend.output.clone(),
+        network,
+        tx_type,
+        spend.input.iter().map(|txin| txin.sequence).collect(),
+        spend.version,
+        spend.lock_time,
+        input_idx as u32,
+    )?;
+    if spend.input.iter().any(|txin| !txin.script_sig.is_empty()) {
+        ctx.fields.scriptsigs = spend
+            .input
+            .iter()
+            .map(|txin| txin.script_sig.clone())
+            .collect();
+    }
+    if !hashes_equal(&hash_array(ctx.ctv()?)?, &expected) {
+        return Err(Error::TemplateMismatch);
+    }
+    Ok(ctx)
+}
+
+/// The CTV locking script committing to `hash`: `<hash> OP_NOP4`.
+pub(crate) fn ctv_script(hash: &[u8]) -> Result<ScriptBuf, Error> {
+    let hash = hash_array(hash.to_vec())?;
+    let mut pbf = PushBytesBuf::new();
+    pbf.extend_from_slice(&hash)?;
+    Ok(bitcoin::script::Builder::new()
+        .push_slice(pbf)
+        .push_opcode(OP_NOP4)
+        .into_script())
+}
+
+/// Check that `script` is exactly `OP_PUSHBYTES_32 <hash> OP_NOP4`.
+fn check_minimal_push(script: &Script) -> Result<(), Error> {
+    let bytes = script.as_bytes();
+    let minimal =
+        bytes.len() == 34 && bytes[0] == OP_PUSHBYTES_32.to_u8() && bytes[33] == OP_NOP4.to_u8();
+    if minimal {
+        Ok(())
+    } else {
+        Err(Error::NonMinimalPush(script.to_owned()))
+    }
+}
+
+/// The spend info of a taproot output with `script` as its only leaf.
+pub(crate) fn leaf_spend_info(
+    script: ScriptBuf,
+    internal_key: XOnlyPublicKey,
+) -> Result<TaprootSpendInfo, Error> {
+    TaprootBuilder::new()
+        .add_leaf(0, script)?
+        .finalize(SECP256K1, internal_key)
+        .map_err(|_| Error::UnknownError("Taproot not finalizable".into()))
+}
+
+/// Check that a template hash is 32 bytes, as anything else is a bug in the hashing.
+pub(crate) fn hash_array(hash: Vec<u8>) -> Result<[u8; 32], Error> {
+    let len = hash.len();
+    hash.try_into()
+        .map_err(|_| Error::InvalidHashLength { len })
+}
+
+/// The fields to which a CTV hash commits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fields {
+    pub version: Version,
+    pub locktime: LockTime,
+    pub sequences: Vec<Sequence>,
+    pub outputs: Vec<Output>,
+    pub input_idx: u32,
+
+    /// The output that fees for spending this transaction are deducted from. See
+    /// `Context::fee_output`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee_output_index: Option<usize>,
+
+    /// The scriptSig committed to for each input, by index. Missing entries are empty, and the
+    /// input spending the CTV itself always has an empty scriptSig. See
+    /// `Context::commit_scriptsig`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scriptsigs: Vec<ScriptBuf>,
+
+    /// **Experimental.** A flags byte for proposed CTV variants which commit to one. When
+    /// nonzero, it is hashed as a single byte at the very end of the preimage, after the input
+    /// index. The default of zero is not hashed at all, so the hash is the BIP-119 hash.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub ctv_flags: u8,
+}
+
+fn is_zero(flags: &u8) -> bool {
+    *flags == 0
+}
+
+/// Outputs committed to by a `Ctv`.
+///
+/// Every variant may carry an optional `label` for UI and accounting purposes. Labels are
+/// serialized, but never affect the transaction or the template hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Output {
+    /// Spend a specific amount to a specific address.
+    Address {
+        address: Address<NetworkUnchecked>,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+
+    /// Commit to an `OP_RETURN` output.
+    Data {
+        data: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+
+    /// Commit an `amount` to a nested `Ctv` output. Use this to create a congestion control tree
+    /// or another type of covenant tree.
+    Tree {
+        tree: Box<Context>,
+        amount: Amount,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+}
+
+impl Output {
+    /// A keyless pay-to-anchor (P2A) output, which anyone may spend to bump the fee of the
+    /// transaction through CPFP.
+    pub fn anchor(amount: Amount, network: Network) -> Result<Output, Error> {
+        let address = Address::from_script(&anchor_script(), network)?;
+        Ok(Output::Address {
+            address: address.as_unchecked().clone(),
+            amount,
+            label: Some("anchor".into()),
+        })
+    }
+
+    /// Sweep `amount` to a single `address`. This is just an `Output::Address`, named for the
+    /// common case of a terminal output at the leaf of a tree.
+    pub fn sweep(address: Address<NetworkUnchecked>, amount: Amount) -> Output {
+        Output::Address {
+            address,
+            amount,
+            label: None,
+        }
+    }
+
+    /// Pay `amount` to the P2WPKH address of `pubkey`, which must be compressed.
+    pub fn p2wpkh(pubkey: &PublicKey, amount: Amount, network: Network) -> Result<Output, Error> {
+        let address = Address::p2wpkh(pubkey, network)?;
+        Ok(Output::sweep(address.as_unchecked().clone(), amount))
+    }
+
+    /// Pay `amount` to the key-path-only P2TR address of `internal_key`, tweaked with no script
+    /// tree as in BIP-86.
+    pub fn p2tr_keyonly(internal_key: XOnlyPublicKey, amount: Amount, network: Network) -> Output {
+        let address = Address::p2tr(SECP256K1, internal_key, None, network);
+        Output::sweep(address.as_unchecked().clone(), amount)
+    }
+
+    /// An `Output::Address` from user-facing strings, such as those in a config file. The
+    /// address must be valid for `network`, and the amount must carry a denomination, e.g.
+    /// `"0.001 BTC"` or `"100000 sat"`.
+    pub fn address_from_str(addr: &str, amount: &str, network: Network) -> Result<Output, Error> {
+        let address = addr
+            .parse::<Address<NetworkUnchecked>>()?
+            .require_network(network)?;
+        Ok(Output::Address {
+            address: address.as_unchecked().clone(),
+            amount: Amount::from_str_with_denomination(amount.trim())?,
+            label: None,
+        })
+    }
+
+    /// Map a `TxOut` back to an `Output`. Scripts with a known address type become
+    /// `Output::Address`, and `OP_RETURN` scripts with a single UTF-8 push become `Output::Data`.
+    /// Any other script is an error, as is any mapping which would not produce an identical
+    /// `TxOut`.
+    pub fn from_txout(txout: &TxOut, network: Network) -> Result<Output, Error> {
+        let unsupported = || Error::UnsupportedScript(txout.script_pubkey.clone());
+        let output = if txout.script_pubkey.is_op_return() {
+            let mut instructions = txout.script_pubkey.instructions().skip(1);
+            let data = match (instructions.next(), instructions.next()) {
+                (Some(Ok(Instruction::PushBytes(data))), None) => data.as_bytes().to_vec(),
+                (None, None) => Vec::new(),
+                _ => return Err(unsupported()),
+            };
+            Output::Data {
+                data: String::from_utf8(data).map_err(|_| unsupported())?,
+                label: None,
+            }
+        } else {
+            let address =
+                Address::from_script(&txout.script_pubkey, network).map_err(|_| unsupported())?;
+            Output::Address {
+                address: address.as_unchecked().clone(),
+                amount: txout.value,
+                label: None,
+            }
+        };
+        if output.as_txout(network)? != *txout {
+            return Err(unsupported());
+        }
+        Ok(output)
+    }
+
+    /// Turn this output into a `TxOut` that may be used in a bitcoin `Transaction` struct.
+    pub fn as_txout(&self, network: Network) -> Result<TxOut, Error> {
+        Ok(match self {
+            Output::Address {
+                address, amount, ..
+            } => TxOut {
+                value: *amount,
+                script_pubkey: address.clone().require_network(network)?.script_pubkey(),
+            },
+            Output::Data { data, .. } => {
+                let mut pb = PushBytesBuf::new();
+                pb.extend_from_slice(data.as_bytes())?;
+                TxOut {
+                    value: Amount::ZERO,
+                    script_pubkey: ScriptBuf::new_op_return(&pb),
+                }
+            }
+            Output::Tree { tree, amount, .. } => TxOut {
+                value: *amount,
+                script_pubkey: tree.address()?.script_pubkey(),
+            },
+        })
+    }
+
+    /// The size in bytes of the serialized `TxOut` from `as_txout`: the 8-byte value, plus the
+    /// `script_pubkey` with its length prefix.
+    pub fn serialized_size(&self, network: Network) -> Result<usize, Error> {
+        Ok(self.as_txout(network)?.size())
+    }
+
+    /// Extract amount of final output.
+    pub fn amount(&self) -> Amount {
+        match self {
+            Output::Address { amount, .. } => *amount,
+            Output::Data { .. } => Amount::ZERO,
+            Output::Tree { amount, .. } => *amount,
+        }
+    }
+
+    /// Set the amount of an `Address` or `Tree` output. `Data` outputs always carry zero value,
+    /// so setting their amount is an error.
+    ///
+    /// This only changes the amount committed to by this output; the nested context of a `Tree`
+    /// output is left as it is.
+    pub fn set_amount(&mut self, new_amount: Amount) -> Result<(), Error> {
+        match self {
+            Output::Address { amount, .. } | Output::Tree { amount, .. } => {
+                *amount = new_amount;
+                Ok(())
+            }
+            Output::Data { .. } => Err(Error::CannotSetAmountOnData),
+        }
+    }
+
+    /// The label attached to this output, if any.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Output::Address { label, .. }
+            | Output::Data { label, .. }
+            | Output::Tree { label, .. } => label.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TxType {
+    #[default]
+    Segwit,
+    Taproot {
+        internal_key: XOnlyPublicKey,
+    },
+
+    /// Taproot where the internal key is deliberately spendable, such as a multisig key held by
+    /// all parties. The key path is a cooperative escape hatch, with the CTV script path as the
+    /// fallback. See `Context::cooperative_close`.
+    TaprootWithKeypath {
+        internal_key: XOnlyPublicKey,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util::test_address, TemplateHash};
+
+    use super::*;
+
+    fn address(n: u8) -> Address<NetworkUnchecked> {
+        test_address(n).as_unchecked().clone()
+    }
+
+    fn context(outputs: Vec<Output>) -> Context {
+        Context {
+            network: Network::Regtest,
+            tx_type: TxType::Segwit,
+            nums_label: None,
+            fields: Fields {
+                version: Version::TWO,
+                locktime: LockTime::ZERO,
+                sequences: vec![Sequence::ENABLE_LOCKTIME_NO_RBF],
+                outputs,
+                input_idx: 0,
+                fee_output_index: None,
+                scriptsigs: Vec::new(),
+                ctv_flags: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_labels_do_not_affect_ctv() {
+        let unlabeled = context(vec![Output::Address {
+            address: address(1),
+            amount: Amount::from_sat(10_000),
+            label: None,
+        }]);
+        let labeled = context(vec![Output::Address {
+            address: address(1),
+            amount: Amount::from_sat(10_000),
+            label: Some("alice's payout".into()),
+        }]);
+        assert_eq!(unlabeled.ctv().unwrap(), labeled.ctv().unwrap());
+        assert!(unlabeled.same_address_as(&labeled).unwrap());
+
+        let json = serde_json::to_string(&labeled).unwrap();
+        let roundtrip: Context = serde_json::from_str(&json).unwrap();
+        assert_eq!(roundtrip.fields.outputs[0].label(), Some("alice's payout"));
+
+        let json = serde_json::to_string(&labeled.fields).unwrap();
+        let fields: Fields = serde_json::from_str(&json).unwrap();
+        let internal_key = crate::util::nums_point("fields");
+        let taproot =
+            Context::from_fields(fields, Network::Regtest, TxType::Taproot { internal_key });
+        assert_eq!(taproot.ctv().unwrap(), labeled.ctv().unwrap());
+        assert_eq!(taproot.into_fields(), labeled.fields);
+    }
+
+    #[test]
+    fn test_locking_script_hex() {
+        // The template hash of the first BIP-119 test vector.
+        let hash = "2d28d0672f1d46cb3e86abd7e682d2d3e9961e6c9237157f47d39f0a694bb694";
+        let script = ctv_script(&hex::decode(hash).unwrap()).unwrap();
+        assert_eq!(script.to_hex_string(), format!("20{hash}b3"));
+        assert!(matches!(
+            ctv_script(&[0; 31]),
+            Err(Error::InvalidHashLength { len: 31 })
+        ));
+
+        let ctx = context(vec![Output::sweep(address(1), Amount::from_sat(1_000))]);
+        assert_eq!(
+            ctx.locking_script_hex().unwrap(),
+            format!("20{}b3", hex::encode(ctx.ctv().unwrap()))
+        );
+        ctx.verify_minimal_push().unwrap();
+        let pushdata1 = ScriptBuf::from_hex(&format!("4c20{hash}b3")).unwrap();
+        assert!(matches!(
+            check_minimal_push(&pushdata1),
+            Err(Error::NonMinimalPush(_))
+        ));
+    }
+
+    #[test]
+    fn test_multi_template_taproot() {
+        let templates: Vec<_> = (1..=3)
+            .map(|n| context(vec![Output::sweep(address(n), Amount::from_sat(1_000))]))
+            .collect();
+        let internal_key = crate::util::nums_point("multi");
+        let (address, control_blocks) =
+            multi_template_taproot(templates.clone(), internal_key).unwrap();
+        let output_key =
+            XOnlyPublicKey::from_slice(&address.script_pubkey().as_bytes()[2..]).unwrap();
+        for (template, cb) in templates.iter().zip(&control_blocks) {
+            let script = template.locking_script().unwrap();
+            assert!(cb.verify_taproot_commitment(SECP256K1, output_key, &script));
+        }
+
+        let mut mixed = templates;
+        mixed[1].network = Network::Signet;
+        assert!(matches!(
+            multi_template_taproot(mixed, internal_key),
+            Err(Error::NetworkMismatch(Network::Regtest, Network::Signet))
+        ));
+        assert!(multi_template_taproot(vec![], internal_key).is_err());
+    }
+
+    #[test]
+    fn test_ctv_flags() {
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(1_000))]);
+        let bip119 = ctx.ctv().unwrap();
+        assert_eq!(bip119, ctx.as_tx().unwrap().template_hash(0).unwrap());
+        ctx.fields.ctv_flags = 1;
+        assert_ne!(ctx.ctv().unwrap(), bip119);
+        let spend = &ctx.spending_tx(Txid::all_zeros(), 0).unwrap()[0];
+        assert!(ctx.verify_spend(spend).unwrap());
+
+        let json = serde_json::to_string(&context(vec![])).unwrap();
+        assert!(!json.contains("ctv_flags"));
+    }
+
+    #[test]
+    fn test_ctv_with_output_order() {
+        let a = Output::sweep(address(1), Amount::from_sat(1_000));
+        let b = Output::sweep(address(2), Amount::from_sat(2_000));
+        let ctx = context(vec![a.clone(), b.clone()]);
+        let swapped = context(vec![b, a]);
+        assert_eq!(
+            ctx.ctv_with_output_order(&[1, 0]).unwrap().to_vec(),
+            swapped.ctv().unwrap()
+        );
+        assert_eq!(
+            ctx.ctv_with_output_order(&[0, 1]).unwrap().to_vec(),
+            ctx.ctv().unwrap()
+        );
+        assert_eq!(
+            ctx.hash_with_version(Version::TWO).unwrap().to_vec(),
+            ctx.ctv().unwrap()
+        );
+        let mut truc = ctx.clone();
+        truc.fields.version = Version(3);
+        assert_eq!(
+            ctx.hash_with_version(Version(3)).unwrap().to_vec(),
+            truc.ctv().unwrap()
+        );
+        for order in [&[0, 0][..], &[0], &[0, 2]] {
+            assert!(matches!(
+                ctx.ctv_with_output_order(order),
+                Err(Error::InvalidOutputOrder(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_address_family_matches_set_amount() {
+        let mut ctx = context(vec![
+            Output::sweep(address(1), Amount::from_sat(1_000)),
+            Output::sweep(address(2), Amount::from_sat(2_000)),
+            Output::sweep(address(3), Amount::from_sat(3_000)),
+        ]);
+        ctx.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("family"),
+        };
+        let amounts = [Amount::from_sat(5_000), Amount::from_sat(6_000)];
+        let family = ctx.address_family(1, &amounts).unwrap();
+        for (amount, address) in amounts.iter().zip(family) {
+            let mut expected = ctx.clone();
+            expected.fields.outputs[1].set_amount(*amount).unwrap();
+            assert_eq!(address, expected.address().unwrap());
+        }
+        assert!(ctx.address_family(3, &amounts).is_err());
+    }
+
+    fn branch_leaf(n: u8) -> Context {
+        context(vec![Output::sweep(address(n), Amount::from_sat(10_000))])
+    }
+
+    fn two_branch_tree() -> Context {
+        let tree = |n| Output::Tree {
+            tree: Box::new(branch_leaf(n)),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        };
+        context(vec![tree(1), tree(2)])
+    }
+
+    #[test]
+    fn test_spend_plan_follows_every_tree_output() {
+        let root = two_branch_tree();
+        let funding = OutPoint::null();
+        let plan = root.spend_plan(funding.txid, funding.vout).unwrap();
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan[0].spends, funding);
+        assert_eq!(plan[1].spends, OutPoint::new(plan[0].txid, 0));
+        assert_eq!(plan[2].spends, OutPoint::new(plan[0].txid, 1));
+        for step in &plan {
+            assert_eq!(step.txid, step.transaction.txid());
+        }
+    }
+
+    #[test]
+    fn test_spending_tx_with_progress() {
+        let root = two_branch_tree();
+        let mut reported = Vec::new();
+        let txs = root
+            .spending_tx_with_progress(Txid::all_zeros(), 0, |built, total| {
+                reported.push((built, total))
+            })
+            .unwrap();
+        assert_eq!(txs, root.spending_tx(Txid::all_zeros(), 0).unwrap());
+        assert_eq!(reported, [(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn test_all_template_hashes() {
+        let root = two_branch_tree();
+        let all = root.all_template_hashes().unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].to_vec(), root.ctv().unwrap());
+        assert_eq!(all[1].to_vec(), branch_leaf(1).ctv().unwrap());
+        assert_eq!(all[2].to_vec(), branch_leaf(2).ctv().unwrap());
+    }
+
+    #[test]
+    fn test_tree_addresses() {
+        let root = two_branch_tree();
+        let addresses = root.tree_addresses().unwrap();
+        assert_eq!(addresses.len(), 3);
+        assert_eq!(addresses[0], root.address().unwrap());
+        assert_eq!(addresses[2], branch_leaf(2).address().unwrap());
+    }
+
+    #[test]
+    fn test_owns_script() {
+        let root = two_branch_tree();
+        let owned = branch_leaf(2).address().unwrap().script_pubkey();
+        assert!(root.owns_script(&owned));
+        assert!(root.owns_script(&root.address().unwrap().script_pubkey()));
+        let foreign = branch_leaf(3).address().unwrap().script_pubkey();
+        assert!(!root.owns_script(&foreign));
+
+        let scripts = root.tree_scripts().unwrap();
+        assert_eq!(scripts.len(), 3);
+        assert!(scripts.contains(&owned));
+        assert!(!scripts.contains(&foreign));
+    }
+
+    #[test]
+    fn test_take_subtree() {
+        let root = two_branch_tree();
+        assert_eq!(root.take_subtree(&[]).unwrap(), root);
+        assert_eq!(root.take_subtree(&[1]).unwrap(), branch_leaf(2));
+        assert!(matches!(
+            root.take_subtree(&[2]),
+            Err(Error::InvalidOutputIndex(2))
+        ));
+        assert!(matches!(
+            root.take_subtree(&[1, 0]),
+            Err(Error::NotATree(0))
+        ));
+    }
+
+    #[test]
+    fn test_replace_subtree() {
+        let mut root = two_branch_tree();
+        let replaced = root.replace_subtree(&[1], branch_leaf(3)).unwrap();
+        assert_eq!(replaced, branch_leaf(2));
+        assert_eq!(root.take_subtree(&[1]).unwrap(), branch_leaf(3));
+        assert_eq!(root.take_subtree(&[0]).unwrap(), branch_leaf(1));
+
+        let before = root.clone();
+        assert!(matches!(
+            root.replace_subtree(&[2], branch_leaf(4)),
+            Err(Error::InvalidOutputIndex(2))
+        ));
+        assert!(matches!(
+            root.replace_subtree(&[0, 0], branch_leaf(4)),
+            Err(Error::NotATree(0))
+        ));
+        assert_eq!(root, before);
+    }
+
+    #[test]
+    fn test_find_reused_addresses() {
+        assert!(two_branch_tree().find_reused_addresses().is_empty());
+
+        let leaf = context(vec![
+            Output::sweep(address(1), Amount::from_sat(10_000)),
+            Output::sweep(address(2), Amount::from_sat(10_000)),
+        ]);
+        let root = context(vec![
+            Output::sweep(address(2), Amount::from_sat(5_000)),
+            Output::Tree {
+                tree: Box::new(leaf),
+                amount: Amount::from_sat(21_000),
+                label: None,
+            },
+            Output::sweep(address(3), Amount::from_sat(5_000)),
+            Output::sweep(address(2), Amount::from_sat(6_000)),
+        ]);
+        assert_eq!(root.find_reused_addresses(), [(address(2), vec![0, 2, 4])]);
+    }
+
+    #[test]
+    fn test_iter_bfs() {
+        let deep = context(vec![Output::Tree {
+            tree: Box::new(branch_leaf(1)),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        }]);
+        let root = context(vec![
+            Output::Tree {
+                tree: Box::new(deep.clone()),
+                amount: Amount::from_sat(12_000),
+                label: None,
+            },
+            Output::Tree {
+                tree: Box::new(branch_leaf(2)),
+                amount: Amount::from_sat(11_000),
+                label: None,
+            },
+        ]);
+        let order: Vec<_> = root
+            .iter_bfs()
+            .map(|(depth, node)| (depth, node.clone()))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (0, root.clone()),
+                (1, deep),
+                (1, branch_leaf(2)),
+                (2, branch_leaf(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match_transaction() {
+        let root = two_branch_tree();
+        let funding = root.funding_tx_with_commitment(vec![], None).unwrap();
+        assert_eq!(
+            root.match_transaction(&funding).unwrap(),
+            [TreeMatch::Funded {
+                path: vec![],
+                vout: 0,
+                amount: Amount::from_sat(22_000),
+            }]
+        );
+
+        let txs = root.spending_tx(funding.txid(), 0).unwrap();
+        assert_eq!(
+            root.match_transaction(&txs[0]).unwrap(),
+            [
+                TreeMatch::Spent { path: vec![] },
+                TreeMatch::Funded {
+                    path: vec![0],
+                    vout: 0,
+                    amount: Amount::from_sat(11_000),
+                },
+                TreeMatch::Funded {
+                    path: vec![1],
+                    vout: 1,
+                    amount: Amount::from_sat(11_000),
+                },
+            ]
+        );
+        assert_eq!(
+            root.match_transaction(&txs[2]).unwrap(),
+            [TreeMatch::Spent { path: vec![1] }]
+        );
+
+        let unrelated = branch_leaf(3).spending_tx(Txid::all_zeros(), 0).unwrap();
+        assert!(root.match_transaction(&unrelated[0]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_required_funding_ignores_data() {
+        let leaf = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        let ctx = context(vec![
+            Output::Tree {
+                tree: Box::new(leaf),
+                amount: Amount::from_sat(11_000),
+                label: None,
+            },
+            Output::Data {
+                data: "rollup".into(),
+                label: None,
+            },
+            Output::sweep(address(2), Amount::from_sat(5_000)),
+        ]);
+        assert_eq!(ctx.required_funding().unwrap(), Amount::from_sat(16_000));
+        assert_eq!(ctx.value_at_depth(1).unwrap(), Amount::from_sat(11_000));
+    }
+
+    #[test]
+    fn test_spending_tx_requires_value() {
+        let data = Output::Data {
+            data: "data".into(),
+            label: None,
+        };
+        let ctx = context(vec![data.clone()]);
+        assert!(matches!(
+            ctx.spending_tx(Txid::all_zeros(), 0),
+            Err(Error::NoSpendableOutputs)
+        ));
+        let anchor = Output::anchor(Amount::ZERO, Network::Regtest).unwrap();
+        let ctx = context(vec![data, anchor]);
+        assert_eq!(ctx.spending_tx(Txid::all_zeros(), 0).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_kind() {
+        let leaf = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        assert_eq!(leaf.kind(), ContextKind::Payment);
+        let root = context(vec![Output::Tree {
+            tree: Box::new(leaf),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        }]);
+        assert_eq!(
+            root.kind(),
+            ContextKind::Tree {
+                depth: 1,
+                leaves: 1
+            }
+        );
+        let data = context(vec![Output::Data {
+            data: "kind".into(),
+            label: None,
+        }]);
+        assert_eq!(data.kind(), ContextKind::DataCommitment);
+        assert_eq!(context(vec![]).kind(), ContextKind::Empty);
+    }
+
+    #[test]
+    fn test_inclusion_path() {
+        let leaf = |n| context(vec![Output::sweep(address(n), Amount::from_sat(10_000))]);
+        let tree = |n| Output::Tree {
+            tree: Box::new(leaf(n)),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        };
+        let mut root = context(vec![tree(1), tree(2)]);
+        root.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("inclusion"),
+        };
+        let hash = hash_array(root.ctv().unwrap()).unwrap();
+        let proof = root.inclusion_path(&[1]).unwrap();
+        assert_eq!(proof.len(), 2);
+        assert_eq!(
+            proof[1].template_hash().unwrap().to_vec(),
+            leaf(2).ctv().unwrap()
+        );
+        assert!(verify_inclusion(&hash, &[1], &proof).unwrap());
+        assert!(!verify_inclusion(&hash, &[0], &proof).unwrap());
+        assert!(verify_inclusion(&hash, &[], &proof[..1]).unwrap());
+
+        let mut tampered = proof.clone();
+        tampered[1].template.output[0].value = Amount::from_sat(10_001);
+        assert!(!verify_inclusion(&hash, &[1], &tampered).unwrap());
+        let mut tampered = proof;
+        tampered[0].template.version = Version::ONE;
+        assert!(!verify_inclusion(&hash, &[1], &tampered).unwrap());
+
+        assert!(matches!(
+            root.inclusion_path(&[1, 0]),
+            Err(Error::NotATree(0))
+        ));
+    }
+
+    #[test]
+    fn test_spending_tx_multi_commits_every_sequence() {
+        let mut ctx = context(vec![Output::Address {
+            address: address(1),
+            amount: Amount::from_sat(10_000),
+            label: None,
+        }]);
+        ctx.fields.sequences = vec![Sequence::ZERO, Sequence::from_height(10)];
+        ctx.fields.input_idx = 1;
+        assert!(matches!(
+            ctx.spending_tx(Txid::all_zeros(), 0),
+            Err(Error::InputCountMismatch(_))
+        ));
+        let inputs = [OutPoint::new(Txid::all_zeros(), 0), OutPoint::null()];
+        let txs = ctx.spending_tx_multi(&inputs).unwrap();
+        assert_eq!(txs.len(), 1);
+        assert_eq!(txs[0].input[1].sequence, Sequence::from_height(10));
+        assert!(txs[0].input[0].witness.is_empty());
+        assert!(ctx.verify_spend(&txs[0]).unwrap());
+        assert!(ctx.spending_tx_multi(&inputs[..1]).is_err());
+
+        ctx.fields.sequences.truncate(1);
+        assert!(matches!(
+            ctx.spending_tx(Txid::all_zeros(), 0),
+            Err(Error::InputCountMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_spend_consolidating() {
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        ctx.fields.sequences = vec![Sequence::ZERO, Sequence::from_height(10)];
+        ctx.fields.input_idx = 1;
+        let inputs = [OutPoint::new(Txid::all_zeros(), 0), OutPoint::null()];
+        let ctv_script_pubkey = ctx.address().unwrap().script_pubkey();
+        let utxo = |idx: usize, sats, script_pubkey: &ScriptBuf| {
+            let txout = TxOut {
+                value: Amount::from_sat(sats),
+                script_pubkey: script_pubkey.clone(),
+            };
+            (inputs[idx], txout)
+        };
+        let other = ScriptBuf::new();
+        let consolidated = ctx
+            .spend_consolidating(vec![
+                utxo(0, 4_000, &other),
+                utxo(1, 7_000, &ctv_script_pubkey),
+            ])
+            .unwrap();
+        assert_eq!(consolidated, ctx.spending_tx_multi(&inputs).unwrap()[0]);
+        assert!(matches!(
+            ctx.spend_consolidating(vec![utxo(0, 4_000, &other), utxo(1, 7_000, &other)]),
+            Err(Error::NotCtvUtxo(1))
+        ));
+        assert!(matches!(
+            ctx.spend_consolidating(vec![
+                utxo(0, 4_000, &other),
+                utxo(1, 5_000, &ctv_script_pubkey)
+            ]),
+            Err(Error::Underfunded { .. })
+        ));
+        assert!(matches!(
+            ctx.spend_consolidating(vec![utxo(0, 20_000, &ctv_script_pubkey)]),
+            Err(Error::InputCountMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_context_from_spend() {
+        let ctx = context(vec![
+            Output::sweep(address(1), Amount::from_sat(1_000)),
+            Output::Data {
+                data: "spend".into(),
+                label: None,
+            },
+        ]);
+        let spend = &ctx.spending_tx(Txid::all_zeros(), 0).unwrap()[0];
+        let recovered = context_from_spend(spend, TxType::Segwit, Network::Regtest).unwrap();
+        assert_eq!(recovered, ctx);
+
+        let mut unsigned = spend.clone();
+        unsigned.input[0].witness = Witness::new();
+        assert!(matches!(
+            context_from_spend(&unsigned, TxType::Segwit, Network::Regtest),
+            Err(Error::NoCtvWitness)
+        ));
+    }
+
+    #[test]
+    fn test_spendable_after() {
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(1_000))]);
+        assert_eq!(ctx.spendable_after(100), 101);
+        ctx.fields.sequences = vec![Sequence::from_height(10)];
+        assert_eq!(ctx.spendable_after(100), 110);
+        ctx.fields.locktime = LockTime::from_height(200).unwrap();
+        assert_eq!(ctx.spendable_after(100), 201);
+        assert_eq!(ctx.spendable_after(195), 205);
+        ctx.fields.sequences = vec![Sequence::MAX];
+        assert_eq!(ctx.spendable_after(100), 101);
+    }
+
+    #[test]
+    fn test_max_unroll_delay() {
+        let delayed = |blocks, outputs| {
+            let mut ctx = context(outputs);
+            ctx.fields.sequences = vec![Sequence::from_height(blocks)];
+            ctx
+        };
+        let tree = |tree| Output::Tree {
+            tree: Box::new(tree),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        };
+        let leaf = |n, blocks| {
+            delayed(
+                blocks,
+                vec![Output::sweep(address(n), Amount::from_sat(10_000))],
+            )
+        };
+        let deep = delayed(10, vec![tree(leaf(1, 3))]);
+        let shallow = leaf(2, 20);
+        assert_eq!(deep.max_unroll_delay().unwrap(), 13);
+        let root = delayed(5, vec![tree(deep.clone()), tree(shallow)]);
+        assert_eq!(root.max_unroll_delay().unwrap(), 25);
+        let root = delayed(5, vec![tree(deep), tree(leaf(2, 1))]);
+        assert_eq!(root.max_unroll_delay().unwrap(), 18);
+
+        let mut unenforced = leaf(2, 20);
+        unenforced.fields.version = Version::ONE;
+        assert_eq!(unenforced.max_unroll_delay().unwrap(), 0);
+        let mut second_input = leaf(2, 20);
+        second_input.fields.sequences.insert(0, Sequence::MAX);
+        second_input.fields.input_idx = 1;
+        assert_eq!(second_input.max_unroll_delay().unwrap(), 20);
+        assert_eq!(
+            second_input.spendable_after(100),
+            second_input.max_unroll_delay().unwrap() + 100
+        );
+
+        let mut timed = leaf(3, 0);
+        timed.fields.sequences = vec![Sequence::from_512_second_intervals(2)];
+        let root = delayed(5, vec![tree(timed)]);
+        assert!(matches!(
+            root.max_unroll_delay(),
+            Err(Error::TimeBasedTimelock(_))
+        ));
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let fee_rate = FeeRate::from_sat_per_vb(1).unwrap();
+        let dust = Context::passthrough(
+            address(1),
+            Amount::from_sat(1),
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        assert!(matches!(
+            dust.minimum_funding(fee_rate),
+            Err(Error::DustOutput { index: 0, .. })
+        ));
+
+        let passthrough = Context::passthrough(
+            address(1),
+            Amount::from_sat(10_000),
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        assert_eq!(
+            passthrough,
+            context(vec![Output::sweep(address(1), Amount::from_sat(10_000))])
+        );
+        assert!(
+            Context::passthrough(address(1), Amount::ZERO, Network::Bitcoin, TxType::Segwit)
+                .is_err()
+        );
+        assert_eq!(
+            passthrough.minimum_funding(fee_rate).unwrap(),
+            Amount::from_sat(10_000) + passthrough.spend_fee(fee_rate).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_commit_scriptsig() {
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        ctx.fields.sequences = vec![Sequence::ZERO, Sequence::ZERO];
+        let without = ctx.ctv().unwrap();
+        let script = ScriptBuf::from_bytes(vec![0x51]);
+        assert!(matches!(
+            ctx.commit_scriptsig(0, script.clone()),
+            Err(Error::ScriptSigOnCtvInput)
+        ));
+        assert!(matches!(
+            ctx.commit_scriptsig(2, script.clone()),
+            Err(Error::InvalidInputIndex(2))
+        ));
+        ctx.commit_scriptsig(1, script.clone()).unwrap();
+        assert_ne!(ctx.ctv().unwrap(), without);
+
+        let inputs = [OutPoint::null(), OutPoint::new(Txid::all_zeros(), 0)];
+        let spend = &ctx.spending_tx_multi(&inputs).unwrap()[0];
+        assert!(spend.input[0].script_sig.is_empty());
+        assert_eq!(spend.input[1].script_sig, script);
+        assert_eq!(spend.template_hash(0).unwrap(), ctx.ctv().unwrap());
+        assert!(ctx.verify_spend(spend).unwrap());
+    }
+
+    fn funded_tree() -> Context {
+        let leaf = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        context(vec![Output::Tree {
+            tree: Box::new(leaf),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        }])
+    }
+
+    #[test]
+    fn test_verify_generated_spend() {
+        let mut root = funded_tree();
+        root.verify_generated_spend(Amount::from_sat(12_000))
+            .unwrap();
+        assert!(root
+            .verify_generated_spend(Amount::from_sat(10_000))
+            .is_err());
+
+        root.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("test"),
+        };
+        root.verify_generated_spend(Amount::from_sat(12_000))
+            .unwrap();
+    }
+
+    #[cfg(feature = "verify")]
+    #[test]
+    fn test_verify_consensus() {
+        let root = funded_tree();
+        let funding = TxOut {
+            value: Amount::from_sat(12_000),
+            script_pubkey: root.address().unwrap().script_pubkey(),
+        };
+        let mut spend = root.spending_tx(Txid::all_zeros(), 0).unwrap().remove(0);
+        root.verify_consensus(&spend, &funding).unwrap();
+        spend.input[0].witness = Witness::from_slice(&[vec![0x51]]);
+        assert!(matches!(
+            root.verify_consensus(&spend, &funding),
+            Err(Error::ScriptVerification(_))
+        ));
+    }
+
+    #[test]
+    fn test_assert_address_witness_consistency() {
+        let mut root = funded_tree();
+        root.assert_address_witness_consistency().unwrap();
+        root.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("test"),
+        };
+        root.assert_address_witness_consistency().unwrap();
+
+        let segwit = funded_tree();
+        let script_pubkey = segwit.address().unwrap().script_pubkey();
+        assert!(matches!(
+            root.check_witness_commitment(&root.witness().unwrap(), &script_pubkey),
+            Err(Error::WitnessMismatch)
+        ));
+    }
+
+    #[test]
+    fn test_fee_implied_by() {
+        let root = funded_tree();
+        assert_eq!(
+            root.fee_implied_by(Amount::from_sat(12_000)).unwrap(),
+            Amount::from_sat(1_000)
+        );
+        assert!(matches!(
+            root.fee_implied_by(Amount::from_sat(10_000)),
+            Err(Error::Underfunded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_find_funding_output() {
+        let root = funded_tree();
+        let mut funding = root.funding_tx_with_commitment(vec![], None).unwrap();
+        assert_eq!(
+            root.find_funding_output(&funding).unwrap(),
+            (0, Amount::from_sat(11_000))
+        );
+        funding.output[0].value = Amount::from_sat(10_999);
+        assert!(matches!(
+            root.find_funding_output(&funding),
+            Err(Error::FundingNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_extract_ctv_from_witness() {
+        let mut root = funded_tree();
+        root.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("test"),
+        };
+        let spend = &root.spending_tx(Txid::all_zeros(), 0).unwrap()[0];
+        let witness = &spend.input[0].witness;
+        assert_eq!(
+            extract_ctv_from_witness(witness, root.tx_type).map(|hash| hash.to_vec()),
+            Some(root.ctv().unwrap())
+        );
+        assert_eq!(extract_ctv_from_witness(witness, TxType::Segwit), None);
+    }
+
+    #[test]
+    fn test_witness_with_annex() {
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        assert!(matches!(
+            ctx.witness_with_annex(&[1, 2]),
+            Err(Error::NotTaproot)
+        ));
+
+        ctx.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("annex"),
+        };
+        let witness = ctx.witness().unwrap();
+        let with_annex = ctx.witness_with_annex(&[1, 2]).unwrap();
+        assert_eq!(with_annex.len(), witness.len() + 1);
+        assert_eq!(with_annex.last(), Some(&[0x50, 1, 2][..]));
+        assert_eq!(
+            ctx.witness_with_annex(&[0x50, 1]).unwrap().last(),
+            Some(&[0x50, 1][..])
+        );
+        assert_eq!(
+            extract_ctv_from_witness(&with_annex, ctx.tx_type).map(|hash| hash.to_vec()),
+            Some(ctx.ctv().unwrap())
+        );
+        let script_pubkey = ctx.address().unwrap().script_pubkey();
+        ctx.check_witness_commitment(&with_annex, &script_pubkey)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_cooperative_close_signs_for_output_key() {
+        let keypair = Keypair::from_seckey_slice(SECP256K1, &[1; 32]).unwrap();
+        let mut ctx = context(vec![Output::sweep(address(1), Amount::from_sat(10_000))]);
+        ctx.tx_type = TxType::TaprootWithKeypath {
+            internal_key: keypair.x_only_public_key().0,
+        };
+        let prevout = TxOut {
+            value: Amount::from_sat(11_000),
+            script_pubkey: ctx.address().unwrap().script_pubkey(),
+        };
+        let prevouts = [prevout];
+        let tx = ctx
+            .cooperative_close(&keypair, &prevouts, Txid::all_zeros(), 0)
+            .unwrap();
+
+        let sighash = SighashCache::new(&tx)
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .unwrap();
+        let msg = Message::from_digest(sighash.to_byte_array());
+        let sig = taproot::Signature::from_slice(&tx.input[0].witness[0]).unwrap();
+        let output_key =
+            XOnlyPublicKey::from_slice(&prevouts[0].script_pubkey.as_bytes()[2..]).unwrap();
+        SECP256K1
+            .verify_schnorr(&sig.sig, &msg, &output_key)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_address_from_str() {
+        let addr = address(1).assume_checked().to_string();
+        let output = Output::address_from_str(&addr, "0.001 BTC", Network::Regtest).unwrap();
+        assert_eq!(output, Output::sweep(address(1), Amount::from_sat(100_000)));
+        let output = Output::address_from_str(&addr, "100000 sat", Network::Regtest).unwrap();
+        assert_eq!(output.amount(), Amount::from_sat(100_000));
+        assert!(Output::address_from_str(&addr, "100000", Network::Regtest).is_err());
+        assert!(Output::address_from_str(&addr, "1 BTC", Network::Bitcoin).is_err());
+    }
+
+    #[test]
+    fn test_key_based_outputs() {
+        let keypair = Keypair::from_seckey_slice(SECP256K1, &[1; 32]).unwrap();
+        let amount = Amount::from_sat(1_000);
+        let pubkey = PublicKey::new(keypair.public_key());
+        let txout = Output::p2wpkh(&pubkey, amount, Network::Regtest)
+            .unwrap()
+            .as_txout(Network::Regtest)
+            .unwrap();
+        assert!(txout.script_pubkey.is_p2wpkh());
+        assert_eq!(
+            Output::p2wpkh(&pubkey, amount, Network::Regtest)
+                .unwrap()
+                .serialized_size(Network::Regtest)
+                .unwrap(),
+            31
+        );
+        assert!(Output::p2wpkh(
+            &PublicKey::new_uncompressed(keypair.public_key()),
+            amount,
+            Network::Regtest
+        )
+        .is_err());
+
+        let xonly = keypair.x_only_public_key().0;
+        let txout = Output::p2tr_keyonly(xonly, amount, Network::Regtest)
+            .as_txout(Network::Regtest)
+            .unwrap();
+        let (output_key, _) = xonly.tap_tweak(SECP256K1, None);
+        assert_eq!(txout.script_pubkey, ScriptBuf::new_p2tr_tweaked(output_key));
+        assert_eq!(txout.value, amount);
+    }
+
+    #[test]
+    fn test_signet_addresses() {
+        let mut ctx = context(vec![Output::Data {
+            data: "signet".into(),
+            label: None,
+        }]);
+        ctx.network = Network::Signet;
+        let address = ctx.address().unwrap();
+        assert!(address.to_string().starts_with("tb1q"));
+        assert!(address.as_unchecked().is_valid_for_network(Network::Signet));
+        assert!(ctx.taproot_output_key().is_err());
+
+        ctx.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("signet"),
+        };
+        let address = ctx.address().unwrap();
+        assert!(address.to_string().starts_with("tb1p"));
+        assert!(address.as_unchecked().is_valid_for_network(Network::Signet));
+        let output_key = ctx.taproot_output_key().unwrap();
+        assert_eq!(
+            address.script_pubkey().as_bytes()[2..],
+            output_key.serialize()
+        );
+
+        let internal_key = ctx.derived_nums_key().unwrap();
+        ctx.tx_type = TxType::Taproot { internal_key };
+        assert_eq!(ctx.derived_nums_key().unwrap(), internal_key);
+        let proof = UnspendabilityProof {
+            label: hex::encode(ctx.ctv().unwrap()),
+            internal_key,
+        };
+        assert!(proof.verify());
+    }
+
+    #[test]
+    fn test_rebalance_scales_subtrees() {
+        let tree = |n| Output::Tree {
+            tree: Box::new(context(vec![
+                Output::sweep(address(n), Amount::from_sat(4_000)),
+                Output::sweep(address(n + 1), Amount::from_sat(6_000)),
+            ])),
+            amount: Amount::from_sat(11_000),
+            label: None,
+        };
+        let mut root = context(vec![tree(1), tree(3)]);
+        let original = root.clone();
+        let err = root.rebalance(&[(0, Amount::from_sat(12_000))]);
+        assert!(matches!(err, Err(Error::RebalanceMismatch { .. })));
+        assert_eq!(root, original);
+
+        root.rebalance(&[(0, Amount::from_sat(16_000)), (1, Amount::from_sat(6_000))])
+            .unwrap();
+        assert_eq!(root.required_funding().unwrap(), Amount::from_sat(22_000));
+        let Output::Tree { tree, .. } = &root.fields.outputs[0] else {
+            unreachable!()
+        };
+        let amounts: Vec<_> = tree.fields.outputs.iter().map(Output::amount).collect();
+        assert_eq!(amounts, [Amount::from_sat(6_000), Amount::from_sat(9_000)]);
+
+        let rescaled = root.rescale(Amount::from_sat(44_000)).unwrap();
+        assert_eq!(
+            rescaled.required_funding().unwrap(),
+            Amount::from_sat(44_000)
+        );
+        rescaled
+            .verify_generated_spend(Amount::from_sat(44_000))
+            .unwrap();
+        assert!(matches!(
+            root.rescale(Amount::from_sat(5_000)),
+            Err(Error::DustOutput { .. })
+        ));
+    }
 }
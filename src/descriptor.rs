@@ -0,0 +1,301 @@
+use bitcoin::{
+    address::NetworkChecked, hashes::hex::FromHex, hex::DisplayHex, Address, Network, ScriptBuf,
+    XOnlyPublicKey,
+};
+use secp256k1::SECP256K1;
+use serde_json::json;
+
+use crate::{
+    ctv::{ctv_script, hash_array, leaf_spend_info},
+    Context, Error, Output, TxType,
+};
+
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u64; 5] = [
+    0xf5dee51989,
+    0xa9fdca3312,
+    0x1bab10e32d,
+    0x3706b1677a,
+    0x644d626ffd,
+];
+
+impl Context {
+    /// A watch-only output descriptor for the address of this CTV, in the form
+    /// `addr(<address>)#<checksum>`.
+    pub fn address_descriptor(&self) -> Result<String, Error> {
+        with_checksum(&format!("addr({})", self.address()?))
+    }
+
+    /// The `address_descriptor` of every context in the tree, depth-first from the root, so a
+    /// watch-only wallet can track the whole unrolling of the tree in one import.
+    pub fn tree_descriptors(&self) -> Result<Vec<String>, Error> {
+        self.nodes()
+            .into_iter()
+            .map(|(_, node)| node.address_descriptor())
+            .collect()
+    }
+
+    /// A request for Bitcoin Core's `importdescriptors` RPC which watches the address of this CTV.
+    /// The RPC accepts an array of these.
+    pub fn core_import_descriptor(&self) -> Result<String, Error> {
+        Ok(json!({
+            "desc": self.address_descriptor()?,
+            "timestamp": "now",
+        })
+        .to_string())
+    }
+
+    /// The top-level outputs of the spend as a JSON array of `{"address": ..., "value": ...}`
+    /// objects, with values in satoshis, as accepted by Electrum and Sparrow style wallets for
+    /// previewing a payment. `Output::Tree` outputs are listed by the address of the nested
+    /// context, and `Output::Data` outputs have no address, so they are skipped.
+    pub fn to_outputs_json(&self) -> Result<String, Error> {
+        let outputs = self
+            .fields
+            .outputs
+            .iter()
+            .filter(|output| !matches!(output, Output::Data { .. }))
+            .map(|output| {
+                let txout = output.as_txout(self.network)?;
+                let address = Address::from_script(&txout.script_pubkey, self.network)?;
+                Ok(json!({
+                    "address": address.to_string(),
+                    "value": txout.value.to_sat(),
+                }))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(serde_json::Value::Array(outputs).to_string())
+    }
+}
+
+/// A watch-only CTV commitment: just the template hash and how it is locked, which is enough to
+/// produce the address but not to spend it. See `from_descriptor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockingContext {
+    pub network: Network,
+    pub tx_type: TxType,
+    pub hash: [u8; 32],
+}
+
+impl LockingContext {
+    pub fn locking_script(&self) -> Result<ScriptBuf, Error> {
+        ctv_script(&self.hash)
+    }
+
+    pub fn address(&self) -> Result<Address<NetworkChecked>, Error> {
+        let script = self.locking_script()?;
+        match self.tx_type {
+            TxType::Segwit => Ok(Address::p2wsh(&script, self.network)),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                let tsi = leaf_spend_info(script, internal_key)?;
+                Ok(Address::p2tr(
+                    SECP256K1,
+                    internal_key,
+                    tsi.merkle_root(),
+                    self.network,
+                ))
+            }
+        }
+    }
+
+    /// The commitment as `wsh(ctv(<hash>))` or `tr(<internal_key>,ctv(<hash>))`, with its
+    /// checksum. `ctv()` is not a standard descriptor fragment, so only `from_descriptor` and
+    /// other CTV-aware tools understand it.
+    pub fn to_descriptor(&self) -> Result<String, Error> {
+        let hash = self.hash.to_lower_hex_string();
+        match self.tx_type {
+            TxType::Segwit => with_checksum(&format!("wsh(ctv({hash}))")),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                with_checksum(&format!("tr({internal_key},ctv({hash}))"))
+            }
+        }
+    }
+}
+
+impl Context {
+    /// The watch-only `LockingContext` for this CTV, e.g. to persist its commitment with
+    /// `LockingContext::to_descriptor`.
+    pub fn locking_context(&self) -> Result<LockingContext, Error> {
+        Ok(LockingContext {
+            network: self.network,
+            tx_type: self.tx_type,
+            hash: hash_array(self.ctv()?)?,
+        })
+    }
+}
+
+/// Parse a `wsh(ctv(<hash>))` or `tr(<internal_key>,ctv(<hash>))` descriptor, as produced by
+/// `LockingContext::to_descriptor`. A `#<checksum>` suffix is optional, but must be correct if
+/// present. Taproot internal keys are taken to be unspendable, as a descriptor cannot say.
+pub fn from_descriptor(s: &str, network: Network) -> Result<LockingContext, Error> {
+    let invalid = || Error::InvalidDescriptor(s.into());
+    let desc = match s.split_once('#') {
+        Some((desc, checksum)) if descriptor_checksum(desc)? == checksum => desc,
+        Some(_) => return Err(invalid()),
+        None => s,
+    };
+    let (tx_type, hash) = if let Some(inner) = strip_fragment(desc, "wsh") {
+        (
+            TxType::Segwit,
+            strip_fragment(inner, "ctv").ok_or_else(invalid)?,
+        )
+    } else if let Some(inner) = strip_fragment(desc, "tr") {
+        let (key, leaf) = inner.split_once(',').ok_or_else(invalid)?;
+        let internal_key = key.parse::<XOnlyPublicKey>().map_err(|_| invalid())?;
+        let hash = strip_fragment(leaf, "ctv").ok_or_else(invalid)?;
+        (TxType::Taproot { internal_key }, hash)
+    } else {
+        return Err(invalid());
+    };
+    Ok(LockingContext {
+        network,
+        tx_type,
+        hash: hash_array(Vec::from_hex(hash)?)?,
+    })
+}
+
+/// The argument of `<name>(<argument>)`.
+fn strip_fragment<'a>(desc: &'a str, name: &str) -> Option<&'a str> {
+    desc.strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Append the BIP-380 checksum to a descriptor, as `<desc>#<checksum>`.
+pub fn with_checksum(desc: &str) -> Result<String, Error> {
+    Ok(format!("{desc}#{}", descriptor_checksum(desc)?))
+}
+
+/// The 8-character BIP-380 checksum of a descriptor (without any existing `#` suffix).
+pub fn descriptor_checksum(desc: &str) -> Result<String, Error> {
+    let mut chk = 1;
+    let mut groups = Vec::with_capacity(3);
+    for ch in desc.chars() {
+        let value = INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| Error::InvalidDescriptor(desc.into()))? as u64;
+        chk = polymod(chk, value & 31);
+        groups.push(value >> 5);
+        if groups.len() == 3 {
+            chk = polymod(chk, groups[0] * 9 + groups[1] * 3 + groups[2]);
+            groups.clear();
+        }
+    }
+    match groups[..] {
+        [a] => chk = polymod(chk, a),
+        [a, b] => chk = polymod(chk, a * 3 + b),
+        _ => {}
+    }
+    for _ in 0..8 {
+        chk = polymod(chk, 0);
+    }
+    chk ^= 1;
+    Ok((0..8)
+        .map(|i| CHECKSUM_CHARSET[((chk >> (5 * (7 - i))) & 31) as usize] as char)
+        .collect())
+}
+
+fn polymod(chk: u64, value: u64) -> u64 {
+    let top = chk >> 35;
+    let mut chk = ((chk & 0x7ffffffff) << 5) ^ value;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            chk ^= generator;
+        }
+    }
+    chk
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{Amount, Network};
+
+    use crate::ContextBuilder;
+
+    use super::*;
+
+    /// Checksums from BIP-380 and the Bitcoin Core descriptor documentation.
+    #[test]
+    fn test_descriptor_checksum() {
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        assert_eq!(
+            with_checksum("addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)").unwrap(),
+            "addr(mkmZxiEcEd8ZqjQWVZuC6so5dFMKEFpN2j)#02wpgw69"
+        );
+        assert!(descriptor_checksum("raw(deadbeef)\u{e9}").is_err());
+    }
+
+    #[test]
+    fn test_from_descriptor_roundtrip() {
+        let mut ctx = ContextBuilder::new(Network::Regtest)
+            .output(Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        for tx_type in [
+            TxType::Segwit,
+            TxType::Taproot {
+                internal_key: crate::util::nums_point("descriptor"),
+            },
+        ] {
+            ctx.tx_type = tx_type;
+            let locking = ctx.locking_context().unwrap();
+            let desc = locking.to_descriptor().unwrap();
+            let parsed = from_descriptor(&desc, Network::Regtest).unwrap();
+            assert_eq!(parsed, locking);
+            assert_eq!(parsed.address().unwrap(), ctx.address().unwrap());
+            let (unchecked, _) = desc.split_once('#').unwrap();
+            assert_eq!(
+                from_descriptor(unchecked, Network::Regtest).unwrap(),
+                locking
+            );
+        }
+        let hash = "00".repeat(32);
+        for bad in [
+            format!("sh(ctv({hash}))"),
+            format!("wsh(ctv({hash})"),
+            format!("wsh(ctv({hash}))#00000000"),
+            "wsh(ctv(00))".to_string(),
+        ] {
+            assert!(from_descriptor(&bad, Network::Regtest).is_err());
+        }
+    }
+
+    #[test]
+    fn test_wallet_exports() {
+        let anchor = Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap();
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .output(Output::Data {
+                data: "skipped".into(),
+                label: None,
+            })
+            .output(anchor.clone())
+            .build()
+            .unwrap();
+        let root = ContextBuilder::new(Network::Regtest)
+            .output(Output::Tree {
+                tree: Box::new(ctx.clone()),
+                amount: Amount::from_sat(240),
+                label: None,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            root.tree_descriptors().unwrap(),
+            [
+                root.address_descriptor().unwrap(),
+                ctx.address_descriptor().unwrap()
+            ]
+        );
+        let Output::Address { address, .. } = anchor else {
+            unreachable!()
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&ctx.to_outputs_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            json!([{ "address": address.assume_checked().to_string(), "value": 240 }])
+        );
+    }
+}
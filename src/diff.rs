@@ -0,0 +1,206 @@
+use bitcoin::{absolute::LockTime, transaction::Version, Amount, Network, ScriptBuf, Sequence};
+
+use crate::{Context, Output, TxType};
+
+/// The differences between two contexts. See `Context::diff`.
+///
+/// Each field is `None` when unchanged, or holds the `(before, after)` values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextDiff {
+    pub network: Option<(Network, Network)>,
+    pub tx_type: Option<(TxType, TxType)>,
+    pub version: Option<(Version, Version)>,
+    pub locktime: Option<(LockTime, LockTime)>,
+    pub sequences: Option<(Vec<Sequence>, Vec<Sequence>)>,
+    pub input_idx: Option<(u32, u32)>,
+    pub fee_output_index: Option<(Option<usize>, Option<usize>)>,
+    pub scriptsigs: Option<(Vec<ScriptBuf>, Vec<ScriptBuf>)>,
+    pub ctv_flags: Option<(u8, u8)>,
+
+    /// Changes to individual outputs, by index.
+    pub outputs: Vec<OutputDiff>,
+
+    /// Whether the template hash changed. If either hash cannot be computed, it is treated as
+    /// changed.
+    pub ctv_changed: bool,
+}
+
+impl ContextDiff {
+    /// Whether the two contexts were identical.
+    pub fn is_empty(&self) -> bool {
+        self.network.is_none()
+            && self.tx_type.is_none()
+            && self.version.is_none()
+            && self.locktime.is_none()
+            && self.sequences.is_none()
+            && self.input_idx.is_none()
+            && self.fee_output_index.is_none()
+            && self.scriptsigs.is_none()
+            && self.ctv_flags.is_none()
+            && self.outputs.is_empty()
+            && !self.ctv_changed
+    }
+}
+
+/// A change to the output at `index`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputDiff {
+    pub index: usize,
+    pub change: OutputChange,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChange {
+    Added(Output),
+    Removed(Output),
+    Changed {
+        before: Output,
+        after: Output,
+    },
+
+    /// Both outputs are `Output::Tree`, but the nested contexts (and maybe amounts) differ.
+    Subtree {
+        amount: Option<(Amount, Amount)>,
+        diff: Box<ContextDiff>,
+    },
+}
+
+impl Context {
+    /// Report every difference between this context and `other`, including within nested trees,
+    /// and whether the template hash changed.
+    pub fn diff(&self, other: &Context) -> ContextDiff {
+        let (before, after) = (&self.fields, &other.fields);
+        let len = before.outputs.len().max(after.outputs.len());
+        let outputs = (0..len)
+            .filter_map(|index| {
+                let change = match (before.outputs.get(index), after.outputs.get(index)) {
+                    (Some(a), Some(b)) if a == b => return None,
+                    (
+                        Some(Output::Tree {
+                            tree: a,
+                            amount: amount_a,
+                            label: label_a,
+                        }),
+                        Some(Output::Tree {
+                            tree: b,
+                            amount: amount_b,
+                            label: label_b,
+                        }),
+                    ) if a != b && label_a == label_b => OutputChange::Subtree {
+                        amount: changed(amount_a, amount_b),
+                        diff: Box::new(a.diff(b)),
+                    },
+                    (Some(a), Some(b)) => OutputChange::Changed {
+                        before: a.clone(),
+                        after: b.clone(),
+                    },
+                    (Some(a), None) => OutputChange::Removed(a.clone()),
+                    (None, Some(b)) => OutputChange::Added(b.clone()),
+                    (None, None) => return None,
+                };
+                Some(OutputDiff { index, change })
+            })
+            .collect();
+        let ctv_changed = match (self.ctv(), other.ctv()) {
+            (Ok(a), Ok(b)) => a != b,
+            _ => true,
+        };
+        ContextDiff {
+            network: changed(&self.network, &other.network),
+            tx_type: changed(&self.tx_type, &other.tx_type),
+            version: changed(&before.version, &after.version),
+            locktime: changed(&before.locktime, &after.locktime),
+            sequences: changed(&before.sequences, &after.sequences),
+            input_idx: changed(&before.input_idx, &after.input_idx),
+            fee_output_index: changed(&before.fee_output_index, &after.fee_output_index),
+            scriptsigs: changed(&before.scriptsigs, &after.scriptsigs),
+            ctv_flags: changed(&before.ctv_flags, &after.ctv_flags),
+            outputs,
+            ctv_changed,
+        }
+    }
+}
+
+fn changed<T: PartialEq + Clone>(before: &T, after: &T) -> Option<(T, T)> {
+    (before != after).then(|| (before.clone(), after.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::Network;
+
+    use crate::{test_util::test_payout, ContextBuilder};
+
+    use super::*;
+
+    fn tree(leaf: Output) -> Context {
+        let leaf = ContextBuilder::new(Network::Regtest)
+            .output(leaf)
+            .build()
+            .unwrap();
+        ContextBuilder::new(Network::Regtest)
+            .output(Output::Tree {
+                tree: Box::new(leaf),
+                amount: Amount::from_sat(11_000),
+                label: None,
+            })
+            .output(test_payout(2, 5_000))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_diff_unchanged() {
+        let ctx = tree(test_payout(1, 10_000));
+        let diff = ctx.diff(&ctx.clone());
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn test_diff_field_change() {
+        let before = tree(test_payout(1, 10_000));
+        let mut after = before.clone();
+        after.fields.locktime = LockTime::from_height(100).unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(diff.locktime, Some((LockTime::ZERO, after.fields.locktime)));
+        assert_eq!(diff.version, None);
+        assert!(diff.outputs.is_empty());
+        assert!(diff.ctv_changed);
+        assert!(!diff.is_empty());
+
+        after.fields.outputs.push(test_payout(3, 1_000));
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.outputs,
+            [OutputDiff {
+                index: 2,
+                change: OutputChange::Added(test_payout(3, 1_000)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_nested_output_change() {
+        let before = tree(test_payout(1, 10_000));
+        let after = tree(test_payout(1, 9_000));
+        let diff = before.diff(&after);
+        assert!(diff.ctv_changed);
+        assert_eq!(diff.outputs.len(), 1);
+        assert_eq!(diff.outputs[0].index, 0);
+        let OutputChange::Subtree { amount, diff } = &diff.outputs[0].change else {
+            panic!("expected a subtree change");
+        };
+        assert_eq!(*amount, None);
+        assert!(diff.ctv_changed);
+        assert_eq!(
+            diff.outputs,
+            [OutputDiff {
+                index: 0,
+                change: OutputChange::Changed {
+                    before: test_payout(1, 10_000),
+                    after: test_payout(1, 9_000),
+                },
+            }]
+        );
+    }
+}
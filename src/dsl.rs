@@ -0,0 +1,179 @@
+use bitcoin::{Amount, Network};
+
+use crate::{Context, ContextBuilder, Error, Output, TxType};
+
+/// Parse a template from a minimal line-based format, one output per line:
+///
+/// ```text
+/// # Comments start with `#` at the start of a line or after whitespace.
+/// pay bcrt1q... 0.5          # an amount in BTC,
+/// pay bcrt1q... 10000 sat    # or with a denomination
+/// data hello world           # an OP_RETURN committing to the rest of the line
+/// tree 0.25 {                # a nested context funded with 0.25 BTC
+///     pay bcrt1q... 0.2499
+/// }
+/// ```
+///
+/// A `#` inside a word is kept, so `data order#42` commits to `order#42`.
+///
+/// Every context uses `network`, `tx_type`, and the `ContextBuilder` defaults for everything
+/// else. Errors carry the line number they were found on.
+pub fn parse_template(src: &str, network: Network, tx_type: TxType) -> Result<Context, Error> {
+    let mut lines = src
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, strip_comment(line).trim()))
+        .filter(|(_, line)| !line.is_empty());
+    parse_block(&mut lines, network, tx_type, None)
+}
+
+/// Parse outputs until the `}` closing the `tree` opened on line `open`, or until the end of
+/// the input for the root context.
+fn parse_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    network: Network,
+    tx_type: TxType,
+    open: Option<usize>,
+) -> Result<Context, Error> {
+    let mut outputs = Vec::new();
+    while let Some((line, text)) = lines.next() {
+        let (keyword, rest) = text
+            .split_once(char::is_whitespace)
+            .map(|(keyword, rest)| (keyword, rest.trim()))
+            .unwrap_or((text, ""));
+        let output = match keyword {
+            "}" if rest.is_empty() => {
+                if open.is_none() {
+                    return Err(parse_error(line, "unmatched `}`"));
+                }
+                return build(outputs, network, tx_type, line);
+            }
+            "pay" => {
+                let (address, amount) = rest
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| parse_error(line, "expected `pay <address> <amount>`"))?;
+                Output::address_from_str(address, &with_denomination(amount.trim()), network)
+                    .map_err(|e| parse_error(line, e))?
+            }
+            "data" => Output::Data {
+                data: rest.into(),
+                label: None,
+            },
+            "tree" => {
+                let amount = rest
+                    .strip_suffix('{')
+                    .ok_or_else(|| parse_error(line, "expected `tree <amount> {`"))?
+                    .trim();
+                let amount = Amount::from_str_with_denomination(&with_denomination(amount))
+                    .map_err(|e| parse_error(line, e))?;
+                let tree = parse_block(lines, network, tx_type, Some(line))?;
+                Output::Tree {
+                    tree: Box::new(tree),
+                    amount,
+                    label: None,
+                }
+            }
+            _ => return Err(parse_error(line, format!("unknown keyword `{keyword}`"))),
+        };
+        outputs.push(output);
+    }
+    match open {
+        Some(line) => Err(parse_error(line, "`tree` is never closed")),
+        None => build(outputs, network, tx_type, 1),
+    }
+}
+
+fn build(
+    outputs: Vec<Output>,
+    network: Network,
+    tx_type: TxType,
+    line: usize,
+) -> Result<Context, Error> {
+    ContextBuilder::new(network)
+        .tx_type(tx_type)
+        .outputs(outputs)
+        .build()
+        .map_err(|e| parse_error(line, e))
+}
+
+/// `line` up to the first `#` which starts it or follows whitespace.
+fn strip_comment(line: &str) -> &str {
+    let mut word_start = true;
+    for (idx, ch) in line.char_indices() {
+        if ch == '#' && word_start {
+            return &line[..idx];
+        }
+        word_start = ch.is_whitespace();
+    }
+    line
+}
+
+/// Amounts without a denomination are in BTC.
+fn with_denomination(amount: &str) -> String {
+    if amount.contains(char::is_whitespace) {
+        amount.into()
+    } else {
+        format!("{amount} BTC")
+    }
+}
+
+fn parse_error(line: usize, reason: impl ToString) -> Error {
+    Error::Parse {
+        line,
+        reason: reason.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::test_address;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_template() {
+        let address = test_address(1);
+        let src = format!(
+            "# a small tree\n\
+             tree 0.001 {{\n\
+                 pay {address} 90000 sat\n\
+             }}\n\
+             data hello world\n"
+        );
+        let ctx = parse_template(&src, Network::Regtest, TxType::Segwit).unwrap();
+        assert_eq!(ctx.fields.outputs.len(), 2);
+        assert_eq!(ctx.required_funding().unwrap(), Amount::from_sat(100_000));
+        assert_eq!(
+            ctx.fields.outputs[1],
+            Output::Data {
+                data: "hello world".into(),
+                label: None
+            }
+        );
+        let Output::Tree { tree, .. } = &ctx.fields.outputs[0] else {
+            panic!("expected a tree");
+        };
+        assert_eq!(tree.required_funding().unwrap(), Amount::from_sat(90_000));
+
+        let ctx = parse_template(
+            "data order#42 # trailing\n  # indented\n",
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        assert_eq!(
+            ctx.fields.outputs,
+            [Output::Data {
+                data: "order#42".into(),
+                label: None
+            }]
+        );
+
+        for (src, line) in [("data x\nfrob\n", 2), ("tree 1 {\ndata x\n", 1), ("}\n", 1)] {
+            match parse_template(src, Network::Regtest, TxType::Segwit) {
+                Err(Error::Parse { line: found, .. }) => assert_eq!(found, line),
+                other => panic!("expected a parse error, got {other:?}"),
+            }
+        }
+    }
+}
@@ -8,15 +8,197 @@ pub enum Error {
     #[error("Missing sequence")]
     MissingSequence,
 
+    #[error("Amount overflow")]
+    AmountOverflow,
+
+    #[error("Unsupported output script: {0}")]
+    UnsupportedScript(bitcoin::ScriptBuf),
+
+    #[error("Internal key is not a known NUMS point: {0}")]
+    SpendableKeypath(bitcoin::XOnlyPublicKey),
+
+    #[error("Context has no spendable key path")]
+    NoKeypath,
+
+    #[error("Context is not taproot")]
+    NotTaproot,
+
+    #[error("Keypair does not match the internal key")]
+    KeypairMismatch,
+
+    #[error("Funding of {funding} is less than the required {required}")]
+    Underfunded {
+        funding: bitcoin::Amount,
+        required: bitcoin::Amount,
+    },
+
+    #[error("Context at {path:?} requires {required}, but is funded with {funding}")]
+    UnderfundedTree {
+        path: Vec<usize>,
+        funding: bitcoin::Amount,
+        required: bitcoin::Amount,
+    },
+
+    #[error("Output {index} of the context at {path:?} is below the dust limit: {amount}")]
+    DustOutput {
+        path: Vec<usize>,
+        index: usize,
+        amount: bitcoin::Amount,
+    },
+
+    #[error("Amount {amount} cannot cover the fee of {fee}")]
+    InsufficientAmount {
+        amount: bitcoin::Amount,
+        fee: bitcoin::Amount,
+    },
+
+    #[error("Rebalancing changes the total from {before} to {after}")]
+    RebalanceMismatch {
+        before: bitcoin::Amount,
+        after: bitcoin::Amount,
+    },
+
+    #[error("Context has no value to scale")]
+    NothingToScale,
+
+    #[error("Fees did not converge within {0} iterations")]
+    FeeConvergenceFailed(usize),
+
+    #[error("Cannot set the amount of a data output")]
+    CannotSetAmountOnData,
+
+    #[error("Output {0} is not a tree")]
+    NotATree(usize),
+
+    #[error("No output at index {0}")]
+    InvalidOutputIndex(usize),
+
+    #[error("No output funds the CTV with at least the required amount")]
+    FundingNotFound,
+
+    #[error("Template hash is {len} bytes, not 32")]
+    InvalidHashLength { len: usize },
+
+    #[error("Locking script does not push the template hash with OP_PUSHBYTES_32: {0}")]
+    NonMinimalPush(bitcoin::ScriptBuf),
+
+    #[error("No input of the spend has a CTV witness")]
+    NoCtvWitness,
+
+    #[error("Spend does not match the template hash")]
+    TemplateMismatch,
+
+    #[error("Witness does not commit to the locking script")]
+    WitnessMismatch,
+
+    #[error("Invalid tree branching factor: {0}")]
+    InvalidBranching(usize),
+
+    #[error("No payouts")]
+    NoPayouts,
+
+    #[error("Time-based relative timelock: {0}")]
+    TimeBasedTimelock(bitcoin::Sequence),
+
+    #[error("Output order is not a permutation of the outputs: {0:?}")]
+    InvalidOutputOrder(Vec<usize>),
+
+    #[error("Timeout is not a relative locktime: {0}")]
+    InvalidTimeout(bitcoin::Sequence),
+
+    #[error("No templates given")]
+    NoTemplates,
+
+    #[error("Templates are for different networks: {0} and {1}")]
+    NetworkMismatch(bitcoin::Network, bitcoin::Network),
+
+    #[error("Context must have exactly one tree output to unvault to")]
+    NotAVault,
+
+    #[error("Tree has no depth {0}")]
+    InvalidDepth(usize),
+
+    #[error("Context at {path:?} has {count} OP_RETURN outputs, but at most one is standard")]
+    MultipleOpReturns { path: Vec<usize>, count: usize },
+
+    #[error("Spend has no outputs carrying value")]
+    NoSpendableOutputs,
+
+    #[error("Context at {path:?} commits to a locktime, but every sequence is final")]
+    LocktimeNotEnforced { path: Vec<usize> },
+
+    #[error(
+        "Data output {index} of the context at {path:?} looks like hex, but is committed as text"
+    )]
+    AmbiguousDataEncoding { path: Vec<usize>, index: usize },
+
+    #[error("Invalid input index: {0}")]
+    InvalidInputIndex(usize),
+
+    #[error("The input spending the CTV must have an empty scriptSig")]
+    ScriptSigOnCtvInput,
+
+    #[error("The UTXO spent by input {0} does not pay to the CTV address")]
+    NotCtvUtxo(usize),
+
+    #[error("Fee of {fee} is below the minimum relay fee of {required}")]
+    FeeBelowMinRelay {
+        fee: bitcoin::Amount,
+        required: bitcoin::Amount,
+    },
+
+    #[error("Input count mismatch: {0}")]
+    InputCountMismatch(String),
+
+    #[error("Too many inputs to encode: {0}")]
+    TooManyInputs(usize),
+
+    #[error("Too many outputs to encode: {0}")]
+    TooManyOutputs(usize),
+
+    #[error("Line {line}: {reason}")]
+    Parse { line: usize, reason: String },
+
+    #[error("Invalid descriptor: {0}")]
+    InvalidDescriptor(String),
+
+    #[error("Non-standard transaction at {path:?}: {reason}")]
+    NonStandard { path: Vec<usize>, reason: String },
+
+    #[error("{} validation errors: {0:?}", .0.len())]
+    ValidationErrors(Vec<Error>),
+
     #[error("{0}")]
     BitcoinStackItemSize(#[from] PushBytesError),
 
     #[error("{0}")]
     BitcoinAddress(#[from] bitcoin::address::Error),
 
+    #[error("{0}")]
+    ParseAddress(#[from] bitcoin::address::ParseError),
+
+    #[error("{0}")]
+    ParseAmount(#[from] bitcoin::amount::ParseAmountError),
+
+    #[error("{0}")]
+    Hex(#[from] bitcoin::hashes::hex::HexToBytesError),
+
+    #[error("{0}")]
+    ConsensusDecode(#[from] bitcoin::consensus::encode::Error),
+
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
+
     #[error("{0}")]
     IoError(#[from] std::io::Error),
 
+    #[error("{0}")]
+    Sighash(#[from] bitcoin::sighash::Error),
+
     #[error("{0}")]
     TaprootBuilderError(#[from] bitcoin::taproot::TaprootBuilderError),
+
+    #[cfg(feature = "verify")]
+    #[error("{0}")]
+    ScriptVerification(#[from] bitcoin::consensus::validation::BitcoinconsensusError),
 }
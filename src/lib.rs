@@ -1,11 +1,45 @@
+mod builder;
+mod bundle;
 mod ctv;
+mod descriptor;
+mod diff;
+mod dsl;
 mod error;
+mod psbt;
+mod report;
 
+#[cfg(any(test, feature = "test-util"))]
+mod test_util;
+mod timeout;
 mod tmplhash;
+mod tree;
+mod uri;
+mod validate;
+mod vault;
+#[cfg(feature = "test-util")]
+mod vectors;
 
 /// Useful utility functions.
 pub mod util;
 
-pub use ctv::{Context, Fields, Output, TxType};
+pub use builder::ContextBuilder;
+pub use bundle::{Bundle, BundleTransaction};
+pub use ctv::{
+    context_from_spend, extract_ctv_from_witness, hashes_equal, multi_template_taproot,
+    verify_inclusion, Context, ContextKind, Fields, InclusionLevel, Output, SpendStep, TreeMatch,
+    TxType, UnspendabilityProof,
+};
+pub use descriptor::{descriptor_checksum, from_descriptor, with_checksum, LockingContext};
+pub use diff::{ContextDiff, OutputChange, OutputDiff};
+pub use dsl::parse_template;
 pub use error::Error;
-pub use tmplhash::TemplateHash;
+pub use report::{AmountReport, ContextReport, FlaggedOutput, SequenceInfo, Warning};
+#[cfg(feature = "test-util")]
+pub use test_util::{test_address, test_payout};
+pub use tmplhash::{
+    partial_hash, preimage_layout, template_hash_from_parts, template_preimage,
+    verify_against_reference, CommitFields, PreimageLayout, TemplateHash,
+};
+pub use tree::{exact_split, required_depth, tree_capacity, tree_for_utxo};
+#[cfg(feature = "test-util")]
+pub use vectors::export_test_vectors;
@@ -0,0 +1,77 @@
+use bitcoin::{psbt, taproot::LeafVersion, TxOut};
+
+use crate::{Context, Error, TxType};
+
+impl Context {
+    /// The PSBT input for spending this CTV, with everything needed to include it in a larger
+    /// PSBT. The CTV needs no signature, so `final_script_witness` is already set.
+    ///
+    /// `witness_utxo` assumes the funding UTXO holds exactly `required_funding()`. Update its
+    /// value if the UTXO holds more.
+    pub fn psbt_input(&self) -> Result<psbt::Input, Error> {
+        let mut input = psbt::Input {
+            witness_utxo: Some(TxOut {
+                value: self.required_funding()?,
+                script_pubkey: self.address()?.script_pubkey(),
+            }),
+            final_script_witness: Some(self.witness()?),
+            ..Default::default()
+        };
+        let script = self.locking_script()?;
+        match self.tx_type {
+            TxType::Segwit => input.witness_script = Some(script),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                let tsi = self.taproot_spend_info(internal_key)?;
+                let control_block = tsi
+                    .control_block(&(script.clone(), LeafVersion::TapScript))
+                    .ok_or_else(|| Error::UnknownError("Taproot construction error".into()))?;
+                input.tap_internal_key = Some(internal_key);
+                input.tap_merkle_root = tsi.merkle_root();
+                input
+                    .tap_scripts
+                    .insert(control_block, (script, LeafVersion::TapScript));
+            }
+        }
+        Ok(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, Amount, Network, Txid};
+
+    use crate::{ContextBuilder, Output};
+
+    use super::*;
+
+    #[test]
+    fn test_psbt_input() {
+        let mut ctx = ContextBuilder::new(Network::Regtest)
+            .output(Output::Data {
+                data: "psbt".into(),
+                label: None,
+            })
+            .output(Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        let input = ctx.psbt_input().unwrap();
+        assert_eq!(input.witness_script, Some(ctx.locking_script().unwrap()));
+        assert_eq!(input.witness_utxo.unwrap().value, Amount::from_sat(240));
+
+        ctx.tx_type = TxType::Taproot {
+            internal_key: crate::util::nums_point("psbt"),
+        };
+        let input = ctx.psbt_input().unwrap();
+        let spend = &ctx.spending_tx(Txid::all_zeros(), 0).unwrap()[0];
+        assert_eq!(
+            input.final_script_witness.as_ref(),
+            Some(&spend.input[0].witness)
+        );
+        assert_eq!(
+            input.witness_utxo.unwrap().script_pubkey,
+            ctx.address().unwrap().script_pubkey()
+        );
+        assert_eq!(input.tap_scripts.len(), 1);
+        assert!(input.witness_script.is_none());
+    }
+}
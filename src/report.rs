@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use bitcoin::{locktime::relative, Address, Amount, Sequence};
+
+use crate::{validate::looks_like_hex, Context, Error, Output};
+
+/// A summary of everything about a `Context`, produced by `Context::inspect`.
+#[derive(Debug, Clone)]
+pub struct ContextReport {
+    /// The address which funds the covenant.
+    pub address: Address,
+
+    /// The template hash committed to by the locking script.
+    pub ctv_hash: Vec<u8>,
+
+    /// The minimum amount the funding UTXO must hold.
+    pub required_funding: Amount,
+
+    /// The number of contexts in the tree with no further `Output::Tree` outputs.
+    pub leaf_count: usize,
+
+    /// The number of nested levels below the root context.
+    pub tree_depth: usize,
+
+    /// Any potential problems found in the tree.
+    pub warnings: Vec<Warning>,
+}
+
+/// Potential problems with a `Context` which do not prevent building it, but will likely cause
+/// problems when funding or spending it.
+///
+/// `path` is the list of output indices leading from the root context to the affected context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// An output is below the dust limit and will not be relayed.
+    Dust {
+        path: Vec<usize>,
+        index: usize,
+        amount: Amount,
+    },
+
+    /// A nested context commits to exactly the amount it is funded with, leaving nothing for fees.
+    NoFeeHeadroom { path: Vec<usize> },
+
+    /// A nested context commits to more than the amount it is funded with, so it can never be spent.
+    Overspend {
+        path: Vec<usize>,
+        required: Amount,
+        available: Amount,
+    },
+
+    /// A nonzero locktime is committed to, but every sequence is final, so it will not be enforced.
+    LocktimeNotEnforced { path: Vec<usize> },
+
+    /// A data output looks like hex, but is committed to as the UTF-8 bytes of the string. See
+    /// `Context::validate_data_outputs`.
+    AmbiguousDataEncoding { path: Vec<usize>, index: usize },
+}
+
+/// Amounts which are a multiple of this are considered round numbers by
+/// `Context::amount_uniqueness_report`.
+const ROUND_AMOUNT: Amount = Amount::from_sat(10_000);
+
+/// An output flagged by `Context::amount_uniqueness_report`.
+///
+/// `path` is the list of output indices leading from the root context to the context holding the
+/// output, and `index` is the output's index within it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlaggedOutput {
+    pub path: Vec<usize>,
+    pub index: usize,
+    pub amount: Amount,
+}
+
+/// How much the amounts in a tree could let an observer link its transactions together,
+/// produced by `Context::amount_uniqueness_report`. `OP_RETURN` outputs are not counted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmountReport {
+    /// The number of outputs with an amount, across every context in the tree.
+    pub outputs: usize,
+
+    /// Outputs with a round-number amount, which stand out from typical change amounts.
+    pub round: Vec<FlaggedOutput>,
+
+    /// Outputs whose amount appears nowhere else in the tree, which fingerprint the output once
+    /// it is seen on chain.
+    pub unique: Vec<FlaggedOutput>,
+}
+
+/// How the sequence committed to for one input is interpreted, produced by
+/// `Context::sequence_summary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceInfo {
+    /// The index of the input in the committed transaction.
+    pub input: usize,
+    pub sequence: Sequence,
+
+    /// Whether the sequence signals replaceability under BIP-125.
+    pub signals_rbf: bool,
+
+    /// Whether the sequence is final, which disables the absolute `locktime` unless another
+    /// input is non-final.
+    pub is_final: bool,
+
+    /// The relative timelock the sequence enforces, if any. This is `None` if the template's
+    /// version is below 2, as relative timelocks are only enforced from version 2.
+    pub relative_locktime: Option<relative::LockTime>,
+}
+
+impl Context {
+    /// Interpret each committed sequence of this context, in input order, so the timelock and
+    /// RBF semantics of the template can be audited.
+    pub fn sequence_summary(&self) -> Vec<SequenceInfo> {
+        let enforces_relative = self.fields.version.0 >= 2;
+        self.fields
+            .sequences
+            .iter()
+            .enumerate()
+            .map(|(input, sequence)| SequenceInfo {
+                input,
+                sequence: *sequence,
+                signals_rbf: sequence.is_rbf(),
+                is_final: sequence.is_final(),
+                relative_locktime: sequence
+                    .to_relative_lock_time()
+                    .filter(|_| enforces_relative),
+            })
+            .collect()
+    }
+
+    /// Analyse the amounts committed to across the whole tree for round numbers and amounts
+    /// which are unique to a single output. This does not modify the tree.
+    pub fn amount_uniqueness_report(&self) -> AmountReport {
+        let mut outputs = Vec::new();
+        let mut counts: HashMap<Amount, usize> = HashMap::new();
+        for (path, node) in self.nodes() {
+            for (index, output) in node.fields.outputs.iter().enumerate() {
+                if let Output::Data { .. } = output {
+                    continue;
+                }
+                let amount = output.amount();
+                *counts.entry(amount).or_default() += 1;
+                outputs.push(FlaggedOutput {
+                    path: path.clone(),
+                    index,
+                    amount,
+                });
+            }
+        }
+        AmountReport {
+            outputs: outputs.len(),
+            round: outputs
+                .iter()
+                .filter(|output| {
+                    output.amount > Amount::ZERO
+                        && output.amount.to_sat() % ROUND_AMOUNT.to_sat() == 0
+                })
+                .cloned()
+                .collect(),
+            unique: outputs
+                .into_iter()
+                .filter(|output| counts[&output.amount] == 1)
+                .collect(),
+        }
+    }
+
+    /// Collect a full report about this `Context`, including any warnings about the tree.
+    pub fn inspect(&self) -> Result<ContextReport, Error> {
+        Ok(ContextReport {
+            address: self.address()?,
+            ctv_hash: self.ctv()?,
+            required_funding: self.required_funding()?,
+            leaf_count: self.leaf_count(),
+            tree_depth: self.tree_depth(),
+            warnings: self.warnings()?,
+        })
+    }
+
+    fn warnings(&self) -> Result<Vec<Warning>, Error> {
+        let mut warnings = Vec::new();
+        for (path, node) in self.nodes() {
+            for (index, output) in node.fields.outputs.iter().enumerate() {
+                if let Output::Data { data, .. } = output {
+                    if looks_like_hex(data) {
+                        warnings.push(Warning::AmbiguousDataEncoding {
+                            path: path.clone(),
+                            index,
+                        });
+                    }
+                    continue;
+                }
+                let txout = output.as_txout(node.network)?;
+                if txout.value < txout.script_pubkey.dust_value() {
+                    warnings.push(Warning::Dust {
+                        path: path.clone(),
+                        index,
+                        amount: txout.value,
+                    });
+                }
+                if let Output::Tree { tree, amount, .. } = output {
+                    let required = tree.required_funding()?;
+                    let mut path = path.clone();
+                    path.push(index);
+                    if required > *amount {
+                        warnings.push(Warning::Overspend {
+                            path,
+                            required,
+                            available: *amount,
+                        });
+                    } else if required == *amount {
+                        warnings.push(Warning::NoFeeHeadroom { path });
+                    }
+                }
+            }
+            if node.fields.locktime.to_consensus_u32() != 0
+                && node
+                    .fields
+                    .sequences
+                    .iter()
+                    .all(|seq| *seq == Sequence::MAX)
+            {
+                warnings.push(Warning::LocktimeNotEnforced { path });
+            }
+        }
+        Ok(warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{absolute::LockTime, transaction::Version, Network};
+
+    use crate::{test_util::test_payout, ContextBuilder};
+
+    use super::*;
+
+    fn subtree(tree: Context, sats: u64) -> Output {
+        Output::Tree {
+            tree: Box::new(tree),
+            amount: Amount::from_sat(sats),
+            label: None,
+        }
+    }
+
+    #[test]
+    fn test_inspect() {
+        let leaf = |n| {
+            ContextBuilder::new(Network::Regtest)
+                .output(test_payout(n, 10_000))
+                .build()
+                .unwrap()
+        };
+        let root = ContextBuilder::new(Network::Regtest)
+            .output(subtree(leaf(1), 11_000))
+            .output(subtree(leaf(2), 11_000))
+            .build()
+            .unwrap();
+        let report = root.inspect().unwrap();
+        assert_eq!(report.address, root.address().unwrap());
+        assert_eq!(report.ctv_hash, root.ctv().unwrap());
+        assert_eq!(report.required_funding, Amount::from_sat(22_000));
+        assert_eq!(report.leaf_count, 2);
+        assert_eq!(report.tree_depth, 1);
+        assert!(report.warnings.is_empty());
+
+        let locked = ContextBuilder::new(Network::Regtest)
+            .locktime(LockTime::from_height(100).unwrap())
+            .sequences(vec![Sequence::MAX])
+            .output(test_payout(3, 100))
+            .build()
+            .unwrap();
+        let root = ContextBuilder::new(Network::Regtest)
+            .output(subtree(leaf(1), 10_000))
+            .output(subtree(leaf(2), 9_000))
+            .output(subtree(locked, 1_000))
+            .output(Output::Data {
+                data: "cafe".into(),
+                label: None,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            root.inspect().unwrap().warnings,
+            [
+                Warning::NoFeeHeadroom { path: vec![0] },
+                Warning::Overspend {
+                    path: vec![1],
+                    required: Amount::from_sat(10_000),
+                    available: Amount::from_sat(9_000),
+                },
+                Warning::AmbiguousDataEncoding {
+                    path: vec![],
+                    index: 3,
+                },
+                Warning::Dust {
+                    path: vec![2],
+                    index: 0,
+                    amount: Amount::from_sat(100),
+                },
+                Warning::LocktimeNotEnforced { path: vec![2] },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sequence_summary() {
+        let sequences = vec![
+            Sequence::MAX,
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            Sequence::from_height(10),
+            Sequence::from_512_second_intervals(3),
+        ];
+        let context = |version| {
+            ContextBuilder::new(Network::Regtest)
+                .version(version)
+                .sequences(sequences.clone())
+                .output(Output::Data {
+                    data: "sequences".into(),
+                    label: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        let summary = context(Version::TWO).sequence_summary();
+        assert_eq!(summary.len(), 4);
+        for (input, info) in summary.iter().enumerate() {
+            assert_eq!(info.input, input);
+            assert_eq!(info.sequence, sequences[input]);
+        }
+        assert!(summary[0].is_final);
+        assert!(!summary[0].signals_rbf);
+        assert_eq!(summary[0].relative_locktime, None);
+        assert!(!summary[1].is_final);
+        assert!(summary[1].signals_rbf);
+        assert_eq!(summary[1].relative_locktime, None);
+        assert!(summary[2].signals_rbf);
+        assert_eq!(
+            summary[2].relative_locktime,
+            Some(relative::LockTime::Blocks(relative::Height::from(10)))
+        );
+        assert_eq!(
+            summary[3].relative_locktime,
+            Some(relative::LockTime::Time(
+                relative::Time::from_512_second_intervals(3)
+            ))
+        );
+
+        let summary = context(Version::ONE).sequence_summary();
+        assert!(summary[1].signals_rbf);
+        assert!(summary.iter().all(|info| info.relative_locktime.is_none()));
+    }
+}
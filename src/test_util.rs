@@ -0,0 +1,17 @@
+use bitcoin::{Address, Amount, Network, ScriptBuf};
+
+use crate::Output;
+
+/// A regtest P2WSH address which is distinct for every `n`, for tests, benches and test
+/// vectors. The witness script is just the byte `n`, so the address is not spendable.
+pub fn test_address(n: u8) -> Address {
+    Address::p2wsh(&ScriptBuf::from_bytes(vec![n]), Network::Regtest)
+}
+
+/// An `Output::sweep` of `sats` to `test_address(n)`.
+pub fn test_payout(n: u8, sats: u64) -> Output {
+    Output::sweep(
+        test_address(n).as_unchecked().clone(),
+        Amount::from_sat(sats),
+    )
+}
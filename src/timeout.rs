@@ -0,0 +1,150 @@
+use bitcoin::{
+    opcodes::all::{OP_CHECKSIG, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_NOP4},
+    script::{Builder, PushBytesBuf},
+    taproot::{self, LeafVersion, TaprootSpendInfo},
+    Address, ScriptBuf, Sequence, Witness, XOnlyPublicKey,
+};
+
+use crate::{
+    ctv::{hash_array, leaf_spend_info},
+    Context, Error, TxType,
+};
+
+impl Context {
+    /// A tapscript which may be spent either through the CTV, or by `fallback_key` once the
+    /// funding output is `timeout` old:
+    ///
+    /// `IF <hash> OP_NOP4 ELSE <timeout> OP_CSV OP_DROP <fallback_key> OP_CHECKSIG ENDIF`
+    ///
+    /// `timeout` must be a relative locktime.
+    pub fn script_with_timeout(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+    ) -> Result<ScriptBuf, Error> {
+        if !timeout.is_relative_lock_time() {
+            return Err(Error::InvalidTimeout(timeout));
+        }
+        let mut hash = PushBytesBuf::new();
+        hash.extend_from_slice(&hash_array(self.ctv()?)?)?;
+        Ok(Builder::new()
+            .push_opcode(OP_IF)
+            .push_slice(hash)
+            .push_opcode(OP_NOP4)
+            .push_opcode(OP_ELSE)
+            .push_sequence(timeout)
+            .push_opcode(OP_CSV)
+            .push_opcode(OP_DROP)
+            .push_x_only_key(&fallback_key)
+            .push_opcode(OP_CHECKSIG)
+            .push_opcode(OP_ENDIF)
+            .into_script())
+    }
+
+    /// The taproot address committing to `script_with_timeout` as its only leaf, under this
+    /// context's internal key. Only taproot contexts may have a timeout path.
+    pub fn address_with_timeout(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+    ) -> Result<Address, Error> {
+        let (_, tsi) = self.timeout_spend_info(timeout, fallback_key)?;
+        Ok(Address::p2tr_tweaked(tsi.output_key(), self.network))
+    }
+
+    /// The witness spending `address_with_timeout` through the CTV branch. The spending
+    /// transaction must still match the template.
+    pub fn timeout_ctv_witness(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+    ) -> Result<Witness, Error> {
+        self.timeout_witness(timeout, fallback_key, vec![vec![1]])
+    }
+
+    /// The witness spending `address_with_timeout` through the timeout branch, given a
+    /// `signature` by `fallback_key` of the spending transaction. The spending input's sequence
+    /// must be at least `timeout`.
+    pub fn timeout_refund_witness(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+        signature: taproot::Signature,
+    ) -> Result<Witness, Error> {
+        self.timeout_witness(timeout, fallback_key, vec![signature.to_vec(), vec![]])
+    }
+
+    fn timeout_witness(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+        branch: Vec<Vec<u8>>,
+    ) -> Result<Witness, Error> {
+        let (script, tsi) = self.timeout_spend_info(timeout, fallback_key)?;
+        let cb = tsi
+            .control_block(&(script.clone(), LeafVersion::TapScript))
+            .ok_or_else(|| Error::UnknownError("Taproot construction error".into()))?;
+        let mut witness = Witness::from_slice(&branch);
+        witness.push(script);
+        witness.push(cb.serialize());
+        Ok(witness)
+    }
+
+    fn timeout_spend_info(
+        &self,
+        timeout: Sequence,
+        fallback_key: XOnlyPublicKey,
+    ) -> Result<(ScriptBuf, TaprootSpendInfo), Error> {
+        let internal_key = match self.tx_type {
+            TxType::Segwit => return Err(Error::NotTaproot),
+            TxType::Taproot { internal_key } | TxType::TaprootWithKeypath { internal_key } => {
+                internal_key
+            }
+        };
+        let script = self.script_with_timeout(timeout, fallback_key)?;
+        let tsi = leaf_spend_info(script.clone(), internal_key)?;
+        Ok((script, tsi))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{taproot::ControlBlock, Network};
+    use secp256k1::SECP256K1;
+
+    use crate::{util::nums_point, ContextBuilder, Output};
+
+    use super::*;
+
+    #[test]
+    fn test_timeout_witness_commits_to_address() {
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .nums_taproot("timeout")
+            .output(Output::Data {
+                data: "timeout".into(),
+                label: None,
+            })
+            .build()
+            .unwrap();
+        let timeout = Sequence::from_height(144);
+        let key = nums_point("fallback");
+        let script = ctx.script_with_timeout(timeout, key).unwrap();
+        assert!(script.to_asm_string().starts_with("OP_IF OP_PUSHBYTES_32"));
+        assert!(ctx.script_with_timeout(Sequence::MAX, key).is_err());
+
+        let witness = ctx.timeout_ctv_witness(timeout, key).unwrap();
+        assert_eq!(witness.len(), 3);
+        assert_eq!(witness.nth(1).unwrap(), script.as_bytes());
+        let cb = ControlBlock::decode(witness.last().unwrap()).unwrap();
+        let address = ctx.address_with_timeout(timeout, key).unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&address.script_pubkey().as_bytes()[2..]);
+        assert!(cb.verify_taproot_commitment(SECP256K1, output_key.unwrap(), &script));
+
+        let mut segwit = ctx.clone();
+        segwit.tx_type = TxType::Segwit;
+        assert!(matches!(
+            segwit.timeout_ctv_witness(timeout, key),
+            Err(Error::NotTaproot)
+        ));
+    }
+}
@@ -1,4 +1,11 @@
-use bitcoin::Transaction;
+use std::ops::{BitOr, Range};
+
+use bitcoin::{
+    absolute::LockTime, consensus, hashes::hex::FromHex, transaction::Version, Amount, Sequence,
+    Transaction, TxIn, TxOut,
+};
+
+use crate::ctv::hash_array;
 
 pub trait TemplateHash {
     fn template_hash(&self, inp_index: u32) -> Result<Vec<u8>, super::Error>;
@@ -7,33 +14,321 @@ pub trait TemplateHash {
 impl TemplateHash for Transaction {
     /// Calculate an `OP_CTV` template hash, given a particular `inp_index`.
     fn template_hash(&self, inp_index: u32) -> Result<Vec<u8>, super::Error> {
-        util::ctv(self, inp_index)
+        util::ctv(self, inp_index, 0)
+    }
+}
+
+/// The byte range of each field in a template hash preimage. See `preimage_layout`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreimageLayout {
+    pub version: Range<usize>,
+    pub locktime: Range<usize>,
+
+    /// The hash of the scriptSigs, which is only present if any scriptSig is non-empty.
+    pub scriptsigs_hash: Option<Range<usize>>,
+    pub input_count: Range<usize>,
+    pub sequences_hash: Range<usize>,
+    pub output_count: Range<usize>,
+    pub outputs_hash: Range<usize>,
+    pub input_index: Range<usize>,
+}
+
+/// A set of the fields committed to by a template hash, for `partial_hash`.
+/// Combine fields with `|`. The input index is always committed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommitFields(u8);
+
+impl CommitFields {
+    pub const VERSION: CommitFields = CommitFields(1 << 0);
+    pub const LOCKTIME: CommitFields = CommitFields(1 << 1);
+
+    /// The hash of the scriptSigs, which BIP-119 only commits to if any is non-empty.
+    pub const SCRIPTSIGS: CommitFields = CommitFields(1 << 2);
+
+    /// The number of inputs.
+    pub const INPUTS: CommitFields = CommitFields(1 << 3);
+    pub const SEQUENCES: CommitFields = CommitFields(1 << 4);
+
+    /// The number of outputs and the hash of the outputs.
+    pub const OUTPUTS: CommitFields = CommitFields(1 << 5);
+
+    /// Every field, giving the BIP-119 hash.
+    pub const ALL: CommitFields = CommitFields(0b11_1111);
+
+    /// Whether every field in `fields` is selected.
+    pub fn contains(self, fields: CommitFields) -> bool {
+        self.0 & fields.0 == fields.0
+    }
+
+    /// These fields, with everything in `fields` removed.
+    pub fn without(self, fields: CommitFields) -> CommitFields {
+        CommitFields(self.0 & !fields.0)
     }
 }
 
-mod util {
-    use std::io::Cursor;
+impl Default for CommitFields {
+    fn default() -> Self {
+        CommitFields::ALL
+    }
+}
+
+impl BitOr for CommitFields {
+    type Output = CommitFields;
+
+    fn bitor(self, rhs: CommitFields) -> CommitFields {
+        CommitFields(self.0 | rhs.0)
+    }
+}
+
+/// Calculate an `OP_CTV` template hash directly from the committed fields, without any
+/// scriptSigs. This is exactly the hash `Context::ctv` produces for the same fields, with no
+/// `ctv_flags`.
+pub fn template_hash_from_parts(
+    version: Version,
+    locktime: LockTime,
+    sequences: &[Sequence],
+    outputs: Vec<TxOut>,
+    input_idx: u32,
+) -> Result<[u8; 32], super::Error> {
+    let tx = template_tx(version, locktime, sequences, outputs);
+    hash_array(util::ctv(&tx, input_idx, 0)?)
+}
+
+/// **Non-standard.** A template hash of `tx` at `inp_index` committing only to the selected
+/// `fields`, for researching CTV variants which commit to less. Only `CommitFields::ALL` produces
+/// the BIP-119 hash; anything else will not be accepted by `OP_CHECKTEMPLATEVERIFY`.
+pub fn partial_hash(
+    tx: &Transaction,
+    inp_index: u32,
+    fields: CommitFields,
+) -> Result<[u8; 32], super::Error> {
+    hash_array(util::partial_ctv(tx, inp_index, fields)?)
+}
+
+/// The bytes hashed to produce the template hash of `tx` at `inp_index`, i.e. its SHA256
+/// preimage.
+pub fn template_preimage(tx: &Transaction, inp_index: u32) -> Result<Vec<u8>, super::Error> {
+    Ok(util::preimage(tx, inp_index)?.0)
+}
+
+/// Where each committed field lies in `template_preimage`, for mapping its bytes to fields.
+pub fn preimage_layout(tx: &Transaction, inp_index: u32) -> Result<PreimageLayout, super::Error> {
+    Ok(util::preimage(tx, inp_index)?.1)
+}
+
+/// Check the template hash of the hex-encoded transaction `tx_hex` at `input_idx` against a
+/// hex-encoded hash computed elsewhere, such as by a reference node.
+pub fn verify_against_reference(
+    tx_hex: &str,
+    input_idx: u32,
+    expected_hash_hex: &str,
+) -> Result<bool, super::Error> {
+    let tx: Transaction = consensus::deserialize(&Vec::from_hex(tx_hex)?)?;
+    let expected = Vec::from_hex(expected_hash_hex)?;
+    Ok(tx.template_hash(input_idx)? == expected)
+}
+
+/// The template hashes of `tx` at `input_idx` with the value of output `varying` set to each of
+/// `amounts` in turn, hashing the fields which do not change only once.
+pub(crate) fn template_hash_family(
+    tx: &Transaction,
+    input_idx: u32,
+    ctv_flags: u8,
+    varying: usize,
+    amounts: &[Amount],
+) -> Result<Vec<Vec<u8>>, super::Error> {
+    util::ctv_family(tx, input_idx, ctv_flags, varying, amounts)
+}
+
+/// The template hash of `tx` at `input_idx`, committing to `ctv_flags` as described on
+/// `Fields::ctv_flags`. With no flags, this is the BIP-119 template hash.
+pub(crate) fn template_hash_with_flags(
+    tx: &Transaction,
+    input_idx: u32,
+    ctv_flags: u8,
+) -> Result<Vec<u8>, super::Error> {
+    util::ctv(tx, input_idx, ctv_flags)
+}
+
+/// The transaction a template hash commits to, with one empty input per sequence.
+pub(crate) fn template_tx(
+    version: Version,
+    locktime: LockTime,
+    sequences: &[Sequence],
+    outputs: Vec<TxOut>,
+) -> Transaction {
+    let input = sequences
+        .iter()
+        .map(|seq| TxIn {
+            sequence: *seq,
+            ..Default::default()
+        })
+        .collect();
+    Transaction {
+        version,
+        lock_time: locktime,
+        input,
+        output: outputs,
+    }
+}
+
+pub(crate) mod util {
     use std::io::Write;
 
-    use bitcoin::{consensus::Encodable, Transaction};
-    use sha2::{Digest, Sha256};
+    use bitcoin::{consensus::Encodable, Amount, Transaction};
 
+    use super::{CommitFields, PreimageLayout};
     use crate::Error;
 
-    pub(super) fn ctv(tx: &Transaction, input: u32) -> Result<Vec<u8>, Error> {
-        let mut buffer = Cursor::new(Vec::<u8>::new());
-        tx.version.consensus_encode(&mut buffer)?;
-        tx.lock_time.consensus_encode(&mut buffer)?;
+    pub(super) fn ctv(tx: &Transaction, input: u32, flags: u8) -> Result<Vec<u8>, Error> {
+        let mut engine = header(tx)?;
+        engine.write_all(&outputs(tx)?)?;
+        footer(&mut engine, input, flags)?;
+        Ok(engine.finalize())
+    }
+
+    /// The template hash of `tx`, hashing only the `fields` selected, in the BIP-119 order.
+    pub(super) fn partial_ctv(
+        tx: &Transaction,
+        input: u32,
+        fields: CommitFields,
+    ) -> Result<Vec<u8>, Error> {
+        let mut engine = Engine::new();
+        if fields.contains(CommitFields::VERSION) {
+            tx.version.consensus_encode(&mut engine)?;
+        }
+        if fields.contains(CommitFields::LOCKTIME) {
+            tx.lock_time.consensus_encode(&mut engine)?;
+        }
+        if fields.contains(CommitFields::SCRIPTSIGS) {
+            if let Some(scriptsigs) = scriptsigs(tx)? {
+                engine.write_all(&scriptsigs)?;
+            }
+        }
+        if fields.contains(CommitFields::INPUTS) {
+            input_count(tx)?.consensus_encode(&mut engine)?;
+        }
+        if fields.contains(CommitFields::SEQUENCES) {
+            engine.write_all(&sequences(tx)?)?;
+        }
+        if fields.contains(CommitFields::OUTPUTS) {
+            output_count(tx)?.consensus_encode(&mut engine)?;
+            engine.write_all(&outputs(tx)?)?;
+        }
+        footer(&mut engine, input, 0)?;
+        Ok(engine.finalize())
+    }
+
+    /// The BIP-119 preimage of the template hash of `tx`, with the range of each field in it.
+    pub(super) fn preimage(
+        tx: &Transaction,
+        input: u32,
+    ) -> Result<(Vec<u8>, PreimageLayout), Error> {
+        let mut buf = Vec::new();
+        let mut start = 0;
+        let mut span = |buf: &Vec<u8>| {
+            let range = start..buf.len();
+            start = buf.len();
+            range
+        };
+        tx.version.consensus_encode(&mut buf)?;
+        let version = span(&buf);
+        tx.lock_time.consensus_encode(&mut buf)?;
+        let locktime = span(&buf);
+        let scriptsigs_hash = match scriptsigs(tx)? {
+            Some(hash) => {
+                buf.extend_from_slice(&hash);
+                Some(span(&buf))
+            }
+            None => None,
+        };
+        input_count(tx)?.consensus_encode(&mut buf)?;
+        let input_count = span(&buf);
+        buf.extend_from_slice(&sequences(tx)?);
+        let sequences_hash = span(&buf);
+        output_count(tx)?.consensus_encode(&mut buf)?;
+        let output_count = span(&buf);
+        buf.extend_from_slice(&outputs(tx)?);
+        let outputs_hash = span(&buf);
+        input.consensus_encode(&mut buf)?;
+        let input_index = span(&buf);
+        let layout = PreimageLayout {
+            version,
+            locktime,
+            scriptsigs_hash,
+            input_count,
+            sequences_hash,
+            output_count,
+            outputs_hash,
+            input_index,
+        };
+        Ok((buf, layout))
+    }
+
+    /// The template hashes of `tx` with the value of output `varying` set to each of `amounts`.
+    /// Everything but the outputs hash is hashed once, as are the outputs before `varying`.
+    pub(super) fn ctv_family(
+        tx: &Transaction,
+        input: u32,
+        flags: u8,
+        varying: usize,
+        amounts: &[Amount],
+    ) -> Result<Vec<Vec<u8>>, Error> {
+        let header = header(tx)?;
+        let mut prefix = Engine::new();
+        for txout in &tx.output[..varying] {
+            txout.consensus_encode(&mut prefix)?;
+        }
+        let mut suffix = Vec::new();
+        for txout in &tx.output[varying + 1..] {
+            txout.consensus_encode(&mut suffix)?;
+        }
+        let script_pubkey = &tx.output[varying].script_pubkey;
+        amounts
+            .iter()
+            .map(|amount| {
+                let mut outputs = prefix.clone();
+                amount.to_sat().consensus_encode(&mut outputs)?;
+                script_pubkey.consensus_encode(&mut outputs)?;
+                outputs.write_all(&suffix)?;
+                let mut engine = header.clone();
+                engine.write_all(&outputs.finalize())?;
+                footer(&mut engine, input, flags)?;
+                Ok(engine.finalize())
+            })
+            .collect()
+    }
+
+    /// An engine which has hashed every field of the template before the outputs hash.
+    fn header(tx: &Transaction) -> Result<Engine, Error> {
+        let mut engine = Engine::new();
+        tx.version.consensus_encode(&mut engine)?;
+        tx.lock_time.consensus_encode(&mut engine)?;
         if let Some(scriptsigs) = scriptsigs(tx)? {
-            buffer.write_all(&scriptsigs)?;
+            engine.write_all(&scriptsigs)?;
+        }
+        input_count(tx)?.consensus_encode(&mut engine)?;
+        engine.write_all(&sequences(tx)?)?;
+        output_count(tx)?.consensus_encode(&mut engine)?;
+        Ok(engine)
+    }
+
+    fn input_count(tx: &Transaction) -> Result<u32, Error> {
+        u32::try_from(tx.input.len()).map_err(|_| Error::TooManyInputs(tx.input.len()))
+    }
+
+    fn output_count(tx: &Transaction) -> Result<u32, Error> {
+        u32::try_from(tx.output.len()).map_err(|_| Error::TooManyOutputs(tx.output.len()))
+    }
+
+    /// Hash the input index, followed by the experimental flags byte only if it is nonzero, so
+    /// that the hash without flags is exactly the BIP-119 hash.
+    fn footer(engine: &mut Engine, input: u32, flags: u8) -> Result<(), Error> {
+        input.consensus_encode(engine)?;
+        if flags != 0 {
+            flags.consensus_encode(engine)?;
         }
-        (tx.input.len() as u32).consensus_encode(&mut buffer)?;
-        buffer.write_all(&sequences(tx)?)?;
-        (tx.output.len() as u32).consensus_encode(&mut buffer)?;
-        buffer.write_all(&outputs(tx)?)?;
-        input.consensus_encode(&mut buffer)?;
-        let buffer = buffer.into_inner();
-        Ok(sha256(buffer))
+        Ok(())
     }
 
     fn scriptsigs(tx: &Transaction) -> Result<Option<Vec<u8>>, Error> {
@@ -42,36 +337,99 @@ mod util {
             return Ok(None);
         }
 
-        let mut cursor = Cursor::new(Vec::new());
+        let mut engine = Engine::new();
         for txin in &tx.input {
-            txin.script_sig.consensus_encode(&mut cursor)?;
+            txin.script_sig.consensus_encode(&mut engine)?;
         }
-        let scripts_sigs = cursor.into_inner();
-        Ok(Some(sha256(scripts_sigs)))
+        Ok(Some(engine.finalize()))
     }
 
     fn sequences(tx: &Transaction) -> Result<Vec<u8>, Error> {
-        let mut sequences = Cursor::new(Vec::new());
+        let mut engine = Engine::new();
         for txin in &tx.input {
-            txin.sequence.consensus_encode(&mut sequences)?;
+            txin.sequence.consensus_encode(&mut engine)?;
         }
-        let sequences = sequences.into_inner();
-        Ok(sha256(sequences))
+        Ok(engine.finalize())
     }
 
     fn outputs(tx: &Transaction) -> Result<Vec<u8>, Error> {
-        let mut cursor = Cursor::new(Vec::new());
+        let mut engine = Engine::new();
         for txout in &tx.output {
-            txout.consensus_encode(&mut cursor)?;
+            txout.consensus_encode(&mut engine)?;
         }
-        let outputs = cursor.into_inner();
-        Ok(sha256(outputs))
+        Ok(engine.finalize())
     }
 
     pub fn sha256(data: Vec<u8>) -> Vec<u8> {
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.finalize().to_vec()
+        let mut engine = Engine::new();
+        engine.input(&data);
+        engine.finalize()
+    }
+
+    /// An incremental SHA256 hasher, so that data can be encoded straight into the hash without
+    /// an intermediate buffer.
+    ///
+    /// Uses the `sha2` crate by default. Disable the default `sha2` feature to use the
+    /// `bitcoin_hashes` implementation already pulled in by `bitcoin` instead.
+    #[derive(Clone)]
+    pub struct Engine {
+        #[cfg(feature = "sha2")]
+        inner: sha2::Sha256,
+        #[cfg(not(feature = "sha2"))]
+        inner: bitcoin::hashes::sha256::HashEngine,
+    }
+
+    #[cfg(feature = "sha2")]
+    impl Engine {
+        pub fn new() -> Self {
+            use sha2::Digest;
+
+            Engine {
+                inner: sha2::Sha256::new(),
+            }
+        }
+
+        pub fn input(&mut self, data: &[u8]) {
+            sha2::Digest::update(&mut self.inner, data);
+        }
+
+        pub fn finalize(self) -> Vec<u8> {
+            sha2::Digest::finalize(self.inner).to_vec()
+        }
+    }
+
+    #[cfg(not(feature = "sha2"))]
+    impl Engine {
+        pub fn new() -> Self {
+            use bitcoin::hashes::{sha256, Hash};
+
+            Engine {
+                inner: sha256::Hash::engine(),
+            }
+        }
+
+        pub fn input(&mut self, data: &[u8]) {
+            bitcoin::hashes::HashEngine::input(&mut self.inner, data);
+        }
+
+        pub fn finalize(self) -> Vec<u8> {
+            use bitcoin::hashes::{sha256, Hash};
+
+            sha256::Hash::from_engine(self.inner)
+                .to_byte_array()
+                .to_vec()
+        }
+    }
+
+    impl Write for Engine {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.input(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
 }
 
@@ -111,6 +469,40 @@ mod tests {
                 // let hash = hex::encode(ctv(&tx, si as u32));
                 let hash = hex::encode(tx.template_hash(si as u32).unwrap());
                 assert_eq!(hash, result[idx]);
+                let partial = partial_hash(&tx, si as u32, CommitFields::default()).unwrap();
+                assert_eq!(hex::encode(partial), result[idx]);
+                let preimage = template_preimage(&tx, si as u32).unwrap();
+                assert_eq!(hex::encode(util::sha256(preimage.clone())), result[idx]);
+                let layout = preimage_layout(&tx, si as u32).unwrap();
+                assert_eq!(layout.outputs_hash.len(), 32);
+                assert_eq!(layout.input_index.end, preimage.len());
+                assert_eq!(preimage[layout.input_index], (si as u32).to_le_bytes());
+                let without_outputs = CommitFields::ALL.without(CommitFields::OUTPUTS);
+                let partial = partial_hash(&tx, si as u32, without_outputs).unwrap();
+                assert_ne!(hex::encode(partial), result[idx]);
+                assert!(verify_against_reference(hex_tx, si as u32, &result[idx]).unwrap());
+            }
+        }
+    }
+
+    /// Witnesses are not committed to, so stripping them must not change the hash.
+    #[test]
+    fn test_ctv_ignores_witness() {
+        let test_data = include_str!("../tests/ctvhash.json");
+        let test_data: Vec<Value> = serde_json::from_str(test_data).unwrap();
+        for td in test_data.iter().filter_map(|td| td.as_object()) {
+            let hex_tx = td["hex_tx"].as_str().unwrap();
+            let tx: Transaction =
+                bitcoin::consensus::deserialize(&hex::decode(hex_tx).unwrap()).unwrap();
+            let mut stripped = tx.clone();
+            for txin in &mut stripped.input {
+                txin.witness.clear();
+            }
+            for idx in 0..tx.input.len() as u32 {
+                assert_eq!(
+                    tx.template_hash(idx).unwrap(),
+                    stripped.template_hash(idx).unwrap()
+                );
             }
         }
     }
@@ -0,0 +1,518 @@
+use bitcoin::{Address, Amount, FeeRate, Network};
+
+use crate::{Context, ContextBuilder, Error, Output, TxType};
+
+/// Build a congestion control tree which splits all of `utxo_value` between `payouts`, in
+/// proportion to each payout's weight.
+///
+/// Payouts are grouped `branching` at a time into leaf contexts, which are grouped again until
+/// the root has at most `branching` outputs. The fee for every transaction in the tree is
+/// reserved at `fee_rate` before the remaining value is allocated, including the fee for the
+/// root transaction itself. Any remainder from rounding goes to the last payout.
+///
+/// If any payout would be below the dust limit of its address, including when the fees leave
+/// nothing for it at all, `Error::DustOutput` is returned.
+pub fn tree_for_utxo(
+    utxo_value: Amount,
+    payouts: Vec<(Address, u64)>,
+    fee_rate: FeeRate,
+    branching: usize,
+    network: Network,
+    tx_type: TxType,
+) -> Result<Context, Error> {
+    if branching < 2 {
+        return Err(Error::InvalidBranching(branching));
+    }
+    if payouts.is_empty() {
+        return Err(Error::NoPayouts);
+    }
+
+    // Transaction weights do not depend on amounts, so the fees can be found up front.
+    let placeholder = payouts
+        .iter()
+        .map(|(address, _)| payout(address, Amount::ZERO))
+        .collect();
+    let fee_for = |tree: &Context| tree.spend_fee(fee_rate);
+    let placeholder = build_tree(placeholder, &fee_for, branching, network, tx_type)?;
+    let fees = placeholder
+        .required_funding()?
+        .checked_add(placeholder.spend_fee(fee_rate)?)
+        .ok_or(Error::AmountOverflow)?;
+    let available = utxo_value.checked_sub(fees).ok_or(Error::Underfunded {
+        funding: utxo_value,
+        required: fees,
+    })?;
+
+    let total_weight: u128 = payouts.iter().map(|(_, weight)| *weight as u128).sum();
+    if total_weight == 0 {
+        return Err(Error::NoPayouts);
+    }
+    let mut allocated = Amount::ZERO;
+    let mut leaves = Vec::with_capacity(payouts.len());
+    for (idx, (address, weight)) in payouts.iter().enumerate() {
+        let amount = if idx == payouts.len() - 1 {
+            available - allocated
+        } else {
+            let sats = available.to_sat() as u128 * *weight as u128 / total_weight;
+            Amount::from_sat(sats as u64)
+        };
+        allocated += amount;
+        leaves.push(payout(address, amount));
+    }
+    let tree = build_tree(leaves, &fee_for, branching, network, tx_type)?;
+    tree.check_dust()?;
+    Ok(tree)
+}
+
+/// Build a tree which pays each of `exact_payouts` exactly, with no change, spending all of
+/// `utxo_value`. Payouts are grouped `branching` at a time as in `tree_for_utxo`.
+///
+/// Everything left over after the payouts is paid as fees, split between the transactions of
+/// the tree in proportion to their weight. Any remainder from rounding goes to the root
+/// transaction. If that leaves any transaction paying less than the minimum relay fee of
+/// 1 sat/vB, it could never be broadcast, so `Error::FeeBelowMinRelay` is returned instead.
+pub fn exact_split(
+    utxo_value: Amount,
+    exact_payouts: Vec<(Address, Amount)>,
+    branching: usize,
+    network: Network,
+    tx_type: TxType,
+) -> Result<Context, Error> {
+    if branching < 2 {
+        return Err(Error::InvalidBranching(branching));
+    }
+    if exact_payouts.is_empty() {
+        return Err(Error::NoPayouts);
+    }
+    let payouts = exact_payouts
+        .iter()
+        .try_fold(Amount::ZERO, |total, (_, amount)| {
+            total.checked_add(*amount)
+        })
+        .ok_or(Error::AmountOverflow)?;
+    let total_fee = utxo_value.checked_sub(payouts).ok_or(Error::Underfunded {
+        funding: utxo_value,
+        required: payouts,
+    })?;
+    let leaves: Vec<_> = exact_payouts
+        .iter()
+        .map(|(address, amount)| payout(address, *amount))
+        .collect();
+
+    // Transaction weights do not depend on amounts, so they can be found up front.
+    let no_fee = |_: &Context| Ok(Amount::ZERO);
+    let placeholder = build_tree(leaves.clone(), &no_fee, branching, network, tx_type)?;
+    let total_weight = placeholder
+        .nodes()
+        .iter()
+        .map(|(_, node)| Ok(node.spend_weight()?.to_wu() as u128))
+        .sum::<Result<u128, Error>>()?;
+    let fee_for = |tree: &Context| {
+        let weight = tree.spend_weight()?;
+        let sats = total_fee.to_sat() as u128 * weight.to_wu() as u128 / total_weight;
+        check_min_relay(tree, Amount::from_sat(sats as u64))
+    };
+    let tree = build_tree(leaves, &fee_for, branching, network, tx_type)?;
+
+    // The root is never passed to `fee_for`, and pays whatever the nested fees leave over.
+    let root_fee = utxo_value
+        .checked_sub(tree.required_funding()?)
+        .ok_or(Error::AmountOverflow)?;
+    check_min_relay(&tree, root_fee)?;
+    Ok(tree)
+}
+
+/// `fee` if it meets the minimum relay fee for spending `tree`, or `Error::FeeBelowMinRelay`.
+fn check_min_relay(tree: &Context, fee: Amount) -> Result<Amount, Error> {
+    let required = FeeRate::BROADCAST_MIN
+        .fee_wu(tree.spend_weight()?)
+        .ok_or(Error::AmountOverflow)?;
+    if fee < required {
+        return Err(Error::FeeBelowMinRelay { fee, required });
+    }
+    Ok(fee)
+}
+
+/// The number of payouts a tree with `branching` outputs per transaction can hold, with `depth`
+/// transactions on the path from the root to each payout. This is one more than the
+/// `Context::tree_depth()` of the tree, which counts only the nested contexts.
+pub fn tree_capacity(depth: usize, branching: usize) -> usize {
+    branching.saturating_pow(depth.try_into().unwrap_or(u32::MAX))
+}
+
+/// The smallest `depth` for which `tree_capacity(depth, branching)` is at least `num_leaves`,
+/// which is the depth `tree_for_utxo` builds for `num_leaves` payouts. A `branching` of less
+/// than two is an error, as no depth could hold more than one payout.
+pub fn required_depth(num_leaves: usize, branching: usize) -> Result<usize, Error> {
+    if branching < 2 {
+        return Err(Error::InvalidBranching(branching));
+    }
+    let mut depth = 1;
+    while tree_capacity(depth, branching) < num_leaves {
+        depth += 1;
+    }
+    Ok(depth)
+}
+
+/// Group `leaves` into a tree of contexts, where each parent funds its children with their
+/// outputs plus the fee `fee_for` returns for spending them.
+fn build_tree(
+    leaves: Vec<Output>,
+    fee_for: &impl Fn(&Context) -> Result<Amount, Error>,
+    branching: usize,
+    network: Network,
+    tx_type: TxType,
+) -> Result<Context, Error> {
+    let mut level = leaves;
+    while level.len() > branching {
+        level = level
+            .chunks(branching)
+            .map(|chunk| {
+                let tree = node(chunk.to_vec(), network, tx_type)?;
+                let amount = tree
+                    .required_funding()?
+                    .checked_add(fee_for(&tree)?)
+                    .ok_or(Error::AmountOverflow)?;
+                Ok(Output::Tree {
+                    tree: Box::new(tree),
+                    amount,
+                    label: None,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+    }
+    node(level, network, tx_type)
+}
+
+fn node(outputs: Vec<Output>, network: Network, tx_type: TxType) -> Result<Context, Error> {
+    ContextBuilder::new(network)
+        .tx_type(tx_type)
+        .outputs(outputs)
+        .build()
+}
+
+fn payout(address: &Address, amount: Amount) -> Output {
+    Output::Address {
+        address: address.as_unchecked().clone(),
+        amount,
+        label: None,
+    }
+}
+
+impl Context {
+    /// Deduct the fee for every transaction in the tree at `fee_rate` from its `fee_output()`,
+    /// leaving the value funding each context unchanged. The root is taken to be funded with
+    /// exactly `required_funding()`.
+    ///
+    /// Amounts change the template hashes of every context above them, so this repeats until a
+    /// pass leaves the tree unchanged, returning `Error::FeeConvergenceFailed` if that does not
+    /// happen within `max_iterations` passes.
+    pub fn converge_fees(
+        &self,
+        fee_rate: FeeRate,
+        max_iterations: usize,
+    ) -> Result<Context, Error> {
+        let funding = self.required_funding()?;
+        let mut current = self.clone();
+        for _ in 0..max_iterations {
+            let mut next = current.clone();
+            next.deduct_fees(funding, &|ctx| ctx.spend_fee(fee_rate))?;
+            if next == current {
+                return Ok(next);
+            }
+            current = next;
+        }
+        Err(Error::FeeConvergenceFailed(max_iterations))
+    }
+
+    /// Deduct a fixed `fee_per_tx` for every transaction in the tree from its `fee_output()`,
+    /// leaving the value funding each context unchanged. By default, the fee output is the first
+    /// `Output::Tree`, so the fees cascade down the tree. The root is taken to be funded with
+    /// exactly `required_funding()`.
+    pub fn apply_flat_fee(&self, fee_per_tx: Amount) -> Result<Context, Error> {
+        let mut ctx = self.clone();
+        ctx.deduct_fees(self.required_funding()?, &|_| Ok(fee_per_tx))?;
+        Ok(ctx)
+    }
+
+    fn deduct_fees(
+        &mut self,
+        funding: Amount,
+        fee_for: &impl Fn(&Context) -> Result<Amount, Error>,
+    ) -> Result<(), Error> {
+        let fee = fee_for(self)?;
+        let target = funding.checked_sub(fee).ok_or(Error::InsufficientAmount {
+            amount: funding,
+            fee,
+        })?;
+        // `fee_output` falls back to the default for an unusable index, but deducting fees from
+        // an output other than the one asked for would be a surprise.
+        if let Some(idx) = self.fields.fee_output_index {
+            if self.fee_output() != Some(idx) {
+                return Err(Error::InvalidOutputIndex(idx));
+            }
+        }
+        let current = self.required_funding()?;
+        if target != current {
+            let idx = self.fee_output().ok_or(Error::NothingToScale)?;
+            let output = self
+                .fields
+                .outputs
+                .get_mut(idx)
+                .ok_or(Error::InvalidOutputIndex(idx))?;
+            let amount = output.amount();
+            let new_amount = amount
+                .checked_add(target)
+                .ok_or(Error::AmountOverflow)?
+                .checked_sub(current)
+                .ok_or(Error::InsufficientAmount { amount, fee })?;
+            output.set_amount(new_amount)?;
+        }
+        for output in &mut self.fields.outputs {
+            if let Output::Tree { tree, amount, .. } = output {
+                tree.deduct_fees(*amount, fee_for)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::{test_address, test_payout};
+
+    use super::*;
+
+    #[test]
+    fn test_tree_for_utxo_spends_entire_utxo() {
+        let utxo_value = Amount::from_sat(1_000_000);
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let payouts = (0..10).map(|n| (test_address(n), n as u64 + 1)).collect();
+        let tree = tree_for_utxo(
+            utxo_value,
+            payouts,
+            fee_rate,
+            3,
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        assert_eq!(tree.tree_depth(), 2);
+        assert_eq!(required_depth(10, 3).unwrap(), tree.tree_depth() + 1);
+        assert_eq!(tree_capacity(3, 3), 27);
+        assert_eq!(required_depth(9, 3).unwrap(), 2);
+        assert_eq!(required_depth(1, 3).unwrap(), 1);
+        assert!(matches!(
+            required_depth(2, 1),
+            Err(Error::InvalidBranching(1))
+        ));
+        assert_eq!(
+            tree.required_funding().unwrap() + tree.spend_fee(fee_rate).unwrap(),
+            utxo_value
+        );
+        tree.verify_generated_spend(utxo_value).unwrap();
+    }
+
+    #[test]
+    fn test_tree_for_utxo_rejects_dust_payouts() {
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let payouts: Vec<_> = [1, 1_000]
+            .into_iter()
+            .enumerate()
+            .map(|(n, weight)| (test_address(n as u8), weight))
+            .collect();
+        let build = |sats| {
+            tree_for_utxo(
+                Amount::from_sat(sats),
+                payouts.clone(),
+                fee_rate,
+                2,
+                Network::Regtest,
+                TxType::Segwit,
+            )
+        };
+        build(1_000_000).unwrap();
+        assert!(matches!(
+            build(100_000),
+            Err(Error::DustOutput { index: 0, .. })
+        ));
+        let fee = build(1_000_000).unwrap().spend_fee(fee_rate).unwrap();
+        assert!(matches!(
+            build(fee.to_sat()),
+            Err(Error::DustOutput { amount, .. }) if amount == Amount::ZERO
+        ));
+    }
+
+    #[test]
+    fn test_exact_split_pays_exact_amounts() {
+        let utxo_value = Amount::from_sat(100_000);
+        let payouts: Vec<_> = (0..5)
+            .map(|n| (test_address(n), Amount::from_sat(19_000)))
+            .collect();
+        let tree = exact_split(
+            utxo_value,
+            payouts.clone(),
+            2,
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        tree.verify_generated_spend(utxo_value).unwrap();
+        let mut fees = tree.fee_implied_by(utxo_value).unwrap();
+        for (_, node) in tree.nodes() {
+            for output in &node.fields.outputs {
+                match output {
+                    Output::Tree { tree, amount, .. } => {
+                        fees += tree.fee_implied_by(*amount).unwrap();
+                    }
+                    _ => assert_eq!(output.amount(), Amount::from_sat(19_000)),
+                }
+            }
+        }
+        assert_eq!(fees, Amount::from_sat(5_000));
+        assert!(matches!(
+            exact_split(
+                Amount::from_sat(90_000),
+                payouts.clone(),
+                2,
+                Network::Regtest,
+                TxType::Segwit,
+            ),
+            Err(Error::Underfunded { .. })
+        ));
+        assert!(matches!(
+            exact_split(
+                Amount::from_sat(95_000),
+                payouts,
+                2,
+                Network::Regtest,
+                TxType::Segwit,
+            ),
+            Err(Error::FeeBelowMinRelay { fee, .. }) if fee == Amount::ZERO
+        ));
+    }
+
+    #[test]
+    fn test_exact_split_checks_root_fee() {
+        let address = test_address(1);
+        let split = |utxo_sats| {
+            exact_split(
+                Amount::from_sat(utxo_sats),
+                vec![(address.clone(), Amount::from_sat(10_000))],
+                2,
+                Network::Regtest,
+                TxType::Segwit,
+            )
+        };
+        assert!(matches!(
+            split(10_000),
+            Err(Error::FeeBelowMinRelay { fee, .. }) if fee == Amount::ZERO
+        ));
+        assert!(matches!(split(10_050), Err(Error::FeeBelowMinRelay { .. })));
+        let tree = split(11_000).unwrap();
+        assert_eq!(tree.tree_depth(), 0);
+        tree.verify_generated_spend(Amount::from_sat(11_000))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_converge_fees() {
+        let fee_rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let leaf = |n| {
+            node(
+                vec![test_payout(n, 50_000)],
+                Network::Regtest,
+                TxType::Segwit,
+            )
+            .unwrap()
+        };
+        let tree = |n| Output::Tree {
+            tree: Box::new(leaf(n)),
+            amount: Amount::from_sat(50_000),
+            label: None,
+        };
+        let root = node(vec![tree(1), tree(2)], Network::Regtest, TxType::Segwit).unwrap();
+        let funding = root.required_funding().unwrap();
+
+        let converged = root.converge_fees(fee_rate, 5).unwrap();
+        converged.verify_generated_spend(funding).unwrap();
+        for output in &converged.fields.outputs {
+            let Output::Tree { tree, amount, .. } = output else {
+                panic!("expected a tree");
+            };
+            assert_eq!(
+                tree.required_funding().unwrap() + tree.spend_fee(fee_rate).unwrap(),
+                *amount
+            );
+        }
+        assert_eq!(
+            converged.required_funding().unwrap() + converged.spend_fee(fee_rate).unwrap(),
+            funding
+        );
+        assert!(matches!(
+            root.converge_fees(fee_rate, 1),
+            Err(Error::FeeConvergenceFailed(1))
+        ));
+    }
+
+    #[test]
+    fn test_apply_flat_fee() {
+        let fee = Amount::from_sat(500);
+        let leaf = |n| {
+            node(
+                vec![test_payout(n, 10_000)],
+                Network::Regtest,
+                TxType::Segwit,
+            )
+            .unwrap()
+        };
+        let child = node(
+            vec![Output::Tree {
+                tree: Box::new(leaf(1)),
+                amount: Amount::from_sat(10_000),
+                label: None,
+            }],
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        let root = node(
+            vec![Output::Tree {
+                tree: Box::new(child),
+                amount: Amount::from_sat(10_000),
+                label: None,
+            }],
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        let adjusted = root.apply_flat_fee(fee).unwrap();
+        let amounts: Vec<_> = adjusted
+            .nodes()
+            .iter()
+            .map(|(_, node)| node.required_funding().unwrap().to_sat())
+            .collect();
+        assert_eq!(amounts, vec![9_500, 9_000, 8_500]);
+        assert!(root.apply_flat_fee(Amount::from_sat(4_000)).is_err());
+    }
+
+    #[test]
+    fn test_fee_output_index_out_of_range() {
+        let mut ctx = node(
+            vec![test_payout(1, 10_000)],
+            Network::Regtest,
+            TxType::Segwit,
+        )
+        .unwrap();
+        ctx.fields.fee_output_index = Some(5);
+        assert!(matches!(
+            ctx.apply_flat_fee(Amount::from_sat(500)),
+            Err(Error::InvalidOutputIndex(5))
+        ));
+        let fee_rate = FeeRate::from_sat_per_vb(1).unwrap();
+        assert!(matches!(
+            ctx.converge_fees(fee_rate, 5),
+            Err(Error::InvalidOutputIndex(5))
+        ));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{Context, Error};
+
+impl Context {
+    /// A BIP-21 URI which pays exactly `required_funding()` to this CTV, e.g. for display as a QR
+    /// code: `bitcoin:<address>?amount=<btc>&label=<label>`.
+    pub fn bip21_uri(&self, label: Option<&str>) -> Result<String, Error> {
+        let mut uri = format!(
+            "bitcoin:{}?amount={}",
+            self.address()?,
+            btc_decimal(self.required_funding()?.to_sat())
+        );
+        if let Some(label) = label {
+            uri.push_str("&label=");
+            uri.push_str(&percent_encode(label));
+        }
+        Ok(uri)
+    }
+}
+
+/// `sats` as a decimal number of BTC, without trailing zeros, as BIP-21 requires.
+fn btc_decimal(sats: u64) -> String {
+    let whole = sats / 100_000_000;
+    let frac = sats % 100_000_000;
+    if frac == 0 {
+        return whole.to_string();
+    }
+    let frac = format!("{frac:08}");
+    format!("{whole}.{}", frac.trim_end_matches('0'))
+}
+
+/// Percent-encode everything but the RFC 3986 unreserved characters.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{Amount, Network};
+
+    use crate::{ContextBuilder, Output};
+
+    use super::*;
+
+    #[test]
+    fn test_bip21_uri() {
+        assert_eq!(btc_decimal(100_000_000), "1");
+        assert_eq!(btc_decimal(150_000), "0.0015");
+        assert_eq!(btc_decimal(1), "0.00000001");
+
+        let ctx = ContextBuilder::new(Network::Regtest)
+            .output(Output::anchor(Amount::from_sat(150_000), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        let uri = ctx.bip21_uri(Some("vault #1")).unwrap();
+        assert_eq!(
+            uri,
+            format!(
+                "bitcoin:{}?amount=0.0015&label=vault%20%231",
+                ctx.address().unwrap()
+            )
+        );
+    }
+}
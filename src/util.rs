@@ -1,15 +1,21 @@
 use bitcoin::XOnlyPublicKey;
-use sha2::Digest;
+
+use crate::tmplhash::util::sha256;
 
 /// Given arbitrary data, hash it until it return a valid secp256k1 public key.
 /// Useful for calculating a NUMS point.
 pub fn hash2curve(data: &[u8]) -> XOnlyPublicKey {
-    let mut hashed = sha2::Sha256::digest(data);
-    let mut pk = XOnlyPublicKey::from_slice(hashed.as_slice()).ok();
+    let mut hashed = sha256(data.to_vec());
+    let mut pk = XOnlyPublicKey::from_slice(&hashed).ok();
 
     while pk.is_none() {
-        hashed = sha2::Sha256::digest(hashed.as_slice());
-        pk = XOnlyPublicKey::from_slice(hashed.as_slice()).ok();
+        hashed = sha256(hashed);
+        pk = XOnlyPublicKey::from_slice(&hashed).ok();
     }
     pk.unwrap()
 }
+
+/// Derive a NUMS point from a human-readable label, for use as an unspendable taproot internal key.
+pub fn nums_point(label: &str) -> XOnlyPublicKey {
+    hash2curve(label.as_bytes())
+}
@@ -0,0 +1,399 @@
+use bitcoin::Weight;
+
+use crate::{Context, Error, Output};
+
+/// Bitcoin Core's `MAX_STANDARD_TX_WEIGHT`.
+const MAX_STANDARD_TX_WEIGHT: Weight = Weight::from_wu(400_000);
+
+/// Bitcoin Core's default `-datacarriersize`, including the `OP_RETURN` and push opcodes.
+const MAX_OP_RETURN_RELAY: usize = 83;
+
+impl Context {
+    /// Check that every transaction in the tree would be relayed under Bitcoin Core's default
+    /// standardness policy: it must commit to at least one input and one output, spend from an
+    /// input index in range, and have a standard version, standard output scripts and a
+    /// standard weight, with at most one `OP_RETURN` output and no dust outputs.
+    pub fn check_bip119_standardness(&self) -> Result<(), Error> {
+        self.check_single_opreturn()?;
+        self.check_dust()?;
+        for (path, node) in self.nodes() {
+            let nonstandard = |reason: String| Error::NonStandard {
+                path: path.clone(),
+                reason,
+            };
+            let fields = &node.fields;
+            if fields.outputs.is_empty() {
+                return Err(nonstandard("no outputs".into()));
+            }
+            if fields.sequences.is_empty() {
+                return Err(nonstandard("no inputs".into()));
+            }
+            if fields.input_idx as usize >= fields.sequences.len() {
+                return Err(nonstandard(format!(
+                    "input index {} out of range for {} inputs",
+                    fields.input_idx,
+                    fields.sequences.len()
+                )));
+            }
+            if !(1..=3).contains(&fields.version.0) {
+                return Err(nonstandard(format!("version {}", fields.version.0)));
+            }
+            for (index, output) in fields.outputs.iter().enumerate() {
+                let script = output.as_txout(node.network)?.script_pubkey;
+                let standard = if script.is_op_return() {
+                    script.len() <= MAX_OP_RETURN_RELAY
+                } else {
+                    script.is_p2pkh() || script.is_p2sh() || script.is_witness_program()
+                };
+                if !standard {
+                    return Err(nonstandard(format!("output {index} script {script}")));
+                }
+            }
+            let weight = node.spend_weight()?;
+            if weight > MAX_STANDARD_TX_WEIGHT {
+                return Err(nonstandard(format!("weight {weight}")));
+            }
+        }
+        Ok(())
+    }
+
+    /// Run every validator over the tree in one pass, before trusting a `Context` loaded from
+    /// untrusted input: nested trees must be funded with at least what they commit to, no
+    /// output may be dust, committed locktimes must be enforced, there must be at most one
+    /// `OP_RETURN` per transaction, input indices must be in range, and every context and
+    /// address must be for the root's network. Any `fee_output_index` must be a value-bearing
+    /// output, and the input spending the CTV must have an empty scriptSig.
+    ///
+    /// Every failing check is reported in `Error::ValidationErrors`.
+    pub fn strict_validate(&self) -> Result<(), Error> {
+        let errors: Vec<Error> = [
+            self.check_amounts(),
+            self.check_dust(),
+            self.check_locktime_enforceable(),
+            self.check_single_opreturn(),
+            self.check_input_indices(),
+            self.check_fee_outputs(),
+            self.check_ctv_scriptsigs(),
+            self.check_networks(),
+        ]
+        .into_iter()
+        .filter_map(Result::err)
+        .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::ValidationErrors(errors))
+        }
+    }
+
+    fn check_amounts(&self) -> Result<(), Error> {
+        for (path, node) in self.nodes() {
+            for (index, output) in node.fields.outputs.iter().enumerate() {
+                if let Output::Tree { tree, amount, .. } = output {
+                    let required = tree.required_funding()?;
+                    if required > *amount {
+                        let mut path = path.clone();
+                        path.push(index);
+                        return Err(Error::UnderfundedTree {
+                            path,
+                            funding: *amount,
+                            required,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_input_indices(&self) -> Result<(), Error> {
+        for (_, node) in self.nodes() {
+            let input_idx = node.fields.input_idx as usize;
+            if input_idx >= node.fields.sequences.len() {
+                return Err(Error::InvalidInputIndex(input_idx));
+            }
+        }
+        Ok(())
+    }
+
+    fn check_fee_outputs(&self) -> Result<(), Error> {
+        for (_, node) in self.nodes() {
+            if let Some(idx) = node.fields.fee_output_index {
+                if node.fee_output() != Some(idx) {
+                    return Err(Error::InvalidOutputIndex(idx));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_ctv_scriptsigs(&self) -> Result<(), Error> {
+        for (_, node) in self.nodes() {
+            let script_sig = node.fields.scriptsigs.get(node.fields.input_idx as usize);
+            if script_sig.is_some_and(|script| !script.is_empty()) {
+                return Err(Error::ScriptSigOnCtvInput);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_networks(&self) -> Result<(), Error> {
+        for (_, node) in self.nodes() {
+            if node.network != self.network {
+                return Err(Error::NetworkMismatch(self.network, node.network));
+            }
+            for output in &node.fields.outputs {
+                if let Output::Address { address, .. } = output {
+                    address.clone().require_network(self.network)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every transaction in the tree committing to a nonzero `locktime` also commits
+    /// to a non-final sequence. If every sequence is final, the locktime is silently ignored.
+    pub fn check_locktime_enforceable(&self) -> Result<(), Error> {
+        for (path, node) in self.nodes() {
+            let fields = &node.fields;
+            if fields.locktime.to_consensus_u32() != 0
+                && fields.sequences.iter().all(|seq| seq.is_final())
+            {
+                return Err(Error::LocktimeNotEnforced { path });
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no `Output::Data` in the tree looks like hex, i.e. is a non-empty, even-length
+    /// string of hex digits. Data is committed to as the UTF-8 bytes of the string, so hex meant
+    /// as raw bytes would produce a different `OP_RETURN` than intended. `Context::inspect`
+    /// reports the same outputs as warnings.
+    pub fn validate_data_outputs(&self) -> Result<(), Error> {
+        for (path, node) in self.nodes() {
+            for (index, output) in node.fields.outputs.iter().enumerate() {
+                if let Output::Data { data, .. } = output {
+                    if looks_like_hex(data) {
+                        return Err(Error::AmbiguousDataEncoding { path, index });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that no transaction in the tree has more than one `OP_RETURN` output, which Bitcoin
+    /// Core will not relay.
+    pub fn check_single_opreturn(&self) -> Result<(), Error> {
+        for (path, node) in self.nodes() {
+            let count = node
+                .fields
+                .outputs
+                .iter()
+                .filter(|output| matches!(output, Output::Data { .. }))
+                .count();
+            if count > 1 {
+                return Err(Error::MultipleOpReturns { path, count });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `data` could be a hex encoding of raw bytes.
+// `usize::is_multiple_of` needs Rust 1.87, which is newer than this crate otherwise requires.
+#[allow(clippy::manual_is_multiple_of)]
+pub(crate) fn looks_like_hex(data: &str) -> bool {
+    !data.is_empty() && data.len() % 2 == 0 && data.chars().all(|ch| ch.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{absolute::LockTime, transaction::Version, Amount, Network, ScriptBuf, Sequence};
+
+    use crate::{ContextBuilder, Warning};
+
+    use super::*;
+
+    #[test]
+    fn test_check_bip119_standardness() {
+        let data = |data: &str| Output::Data {
+            data: data.into(),
+            label: None,
+        };
+        let standard = || ContextBuilder::new(Network::Regtest).output(data("standard"));
+        standard()
+            .build()
+            .unwrap()
+            .check_bip119_standardness()
+            .unwrap();
+
+        let nonstandard = |ctx: Context| ctx.check_bip119_standardness().unwrap_err();
+        let path_of = |err: Error| match err {
+            Error::NonStandard { path, .. } => path,
+            err => panic!("expected a non-standard error, got {err}"),
+        };
+        let ctx = standard().version(Version(4)).build().unwrap();
+        assert_eq!(path_of(nonstandard(ctx)), Vec::<usize>::new());
+        let ctx = standard().input_idx(1).build().unwrap();
+        assert_eq!(path_of(nonstandard(ctx)), Vec::<usize>::new());
+        let ctx = standard().sequences(vec![]).build().unwrap();
+        assert_eq!(path_of(nonstandard(ctx)), Vec::<usize>::new());
+        let oversized = ContextBuilder::new(Network::Regtest)
+            .output(data(&"x".repeat(MAX_OP_RETURN_RELAY)))
+            .build()
+            .unwrap();
+        assert_eq!(path_of(nonstandard(oversized.clone())), Vec::<usize>::new());
+        let nested = ContextBuilder::new(Network::Regtest)
+            .output(Output::Tree {
+                tree: Box::new(oversized),
+                amount: Amount::from_sat(1_000),
+                label: None,
+            })
+            .build()
+            .unwrap();
+        assert_eq!(path_of(nonstandard(nested)), [0]);
+
+        let ctx = standard().output(data("second")).build().unwrap();
+        assert!(matches!(
+            nonstandard(ctx),
+            Error::MultipleOpReturns { count: 2, .. }
+        ));
+
+        let dust = Output::anchor(Amount::from_sat(1), Network::Regtest).unwrap();
+        let ctx = standard().output(dust).build().unwrap();
+        assert!(matches!(
+            nonstandard(ctx),
+            Error::DustOutput { index: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_validate_data_outputs() {
+        let data = |data: &str| {
+            ContextBuilder::new(Network::Regtest)
+                .output(Output::Data {
+                    data: data.into(),
+                    label: None,
+                })
+                .build()
+                .unwrap()
+        };
+        data("hello").validate_data_outputs().unwrap();
+        data("abc").validate_data_outputs().unwrap();
+        let hex = data("deadbeef");
+        assert!(matches!(
+            hex.validate_data_outputs(),
+            Err(Error::AmbiguousDataEncoding { index: 0, .. })
+        ));
+        hex.strict_validate().unwrap();
+        assert!(hex
+            .inspect()
+            .unwrap()
+            .warnings
+            .contains(&Warning::AmbiguousDataEncoding {
+                path: vec![],
+                index: 0
+            }));
+    }
+
+    #[test]
+    fn test_check_locktime_enforceable() {
+        let leaf = |sequence| {
+            ContextBuilder::new(Network::Regtest)
+                .locktime(LockTime::from_height(100).unwrap())
+                .sequences(vec![sequence])
+                .output(Output::Data {
+                    data: "leaf".into(),
+                    label: None,
+                })
+                .build()
+                .unwrap()
+        };
+        let root = |leaf| {
+            ContextBuilder::new(Network::Regtest)
+                .output(Output::Tree {
+                    tree: Box::new(leaf),
+                    amount: Amount::from_sat(1_000),
+                    label: None,
+                })
+                .build()
+                .unwrap()
+        };
+        leaf(Sequence::ENABLE_LOCKTIME_NO_RBF)
+            .check_locktime_enforceable()
+            .unwrap();
+        root(leaf(Sequence::ENABLE_LOCKTIME_NO_RBF))
+            .check_locktime_enforceable()
+            .unwrap();
+        assert!(matches!(
+            leaf(Sequence::MAX).check_locktime_enforceable(),
+            Err(Error::LocktimeNotEnforced { path }) if path.is_empty()
+        ));
+        assert!(matches!(
+            root(leaf(Sequence::MAX)).check_locktime_enforceable(),
+            Err(Error::LocktimeNotEnforced { path }) if path == [0]
+        ));
+
+        let mut unlocked = leaf(Sequence::MAX);
+        unlocked.fields.locktime = LockTime::ZERO;
+        unlocked.check_locktime_enforceable().unwrap();
+    }
+
+    #[test]
+    fn test_strict_validate_reports_underfunded_path() {
+        let leaf = ContextBuilder::new(Network::Regtest)
+            .output(Output::anchor(Amount::from_sat(1_000), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        let tree = |tree, sats| Output::Tree {
+            tree: Box::new(tree),
+            amount: Amount::from_sat(sats),
+            label: None,
+        };
+        let branch = ContextBuilder::new(Network::Regtest)
+            .output(tree(leaf.clone(), 1_000))
+            .output(tree(leaf, 900))
+            .build()
+            .unwrap();
+        let root = ContextBuilder::new(Network::Regtest)
+            .output(tree(branch, 2_000))
+            .build()
+            .unwrap();
+        let Err(Error::ValidationErrors(errors)) = root.strict_validate() else {
+            panic!("expected validation errors");
+        };
+        assert!(matches!(
+            &errors[..],
+            [Error::UnderfundedTree { path, funding, required }]
+                if *path == [0, 1]
+                    && *funding == Amount::from_sat(900)
+                    && *required == Amount::from_sat(1_000)
+        ));
+    }
+
+    #[test]
+    fn test_strict_validate_collects_errors() {
+        let mut ctx = ContextBuilder::new(Network::Regtest)
+            .locktime(LockTime::from_height(100).unwrap())
+            .sequences(vec![Sequence::MAX])
+            .input_idx(1)
+            .output(Output::anchor(Amount::from_sat(240), Network::Regtest).unwrap())
+            .build()
+            .unwrap();
+        ctx.fields.fee_output_index = Some(5);
+        ctx.fields.scriptsigs = vec![ScriptBuf::new(), ScriptBuf::from_bytes(vec![0x51])];
+        let Err(Error::ValidationErrors(errors)) = ctx.strict_validate() else {
+            panic!("expected validation errors");
+        };
+        assert!(matches!(
+            errors[..],
+            [
+                Error::LocktimeNotEnforced { .. },
+                Error::InvalidInputIndex(1),
+                Error::InvalidOutputIndex(5),
+                Error::ScriptSigOnCtvInput,
+            ]
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+use bitcoin::{Transaction, Txid};
+
+use crate::{Context, Error, Output};
+
+impl Context {
+    /// The first stage of a two-stage vault: the transaction spending the vault into its
+    /// intermediate unvaulting context, which is usually timelocked so the unvault can be
+    /// noticed and clawed back. This context must have exactly one `Output::Tree`, the
+    /// intermediate context. See `final_tx` for the second stage.
+    pub fn unvault_tx(&self, txid: Txid, vout: u32) -> Result<Transaction, Error> {
+        self.unvault_output()?;
+        let mut txs = self.spending_tx_to_depth(txid, vout, 0)?;
+        Ok(txs.remove(0))
+    }
+
+    /// The second stage of a two-stage vault: the transaction spending the intermediate context
+    /// created by the `unvault_tx` with `unvault_txid`. Any relative timelock committed to by
+    /// the intermediate context must have passed before it can confirm.
+    pub fn final_tx(&self, unvault_txid: Txid) -> Result<Transaction, Error> {
+        let (vout, intermediate) = self.unvault_output()?;
+        let mut txs = intermediate.spending_tx_to_depth(unvault_txid, vout, 0)?;
+        Ok(txs.remove(0))
+    }
+
+    fn unvault_output(&self) -> Result<(u32, &Context), Error> {
+        let mut trees = self
+            .fields
+            .outputs
+            .iter()
+            .enumerate()
+            .filter_map(|(vout, output)| match output {
+                Output::Tree { tree, .. } => Some((vout as u32, tree.as_ref())),
+                _ => None,
+            });
+        match (trees.next(), trees.next()) {
+            (Some(intermediate), None) => Ok(intermediate),
+            _ => Err(Error::NotAVault),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{hashes::Hash, Amount, Network, OutPoint, Sequence};
+
+    use crate::{test_util::test_address, ContextBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_two_stage_vault() {
+        let cold = test_address(1);
+        let intermediate = ContextBuilder::new(Network::Regtest)
+            .sequences(vec![Sequence::from_height(144)])
+            .output(Output::sweep(
+                cold.as_unchecked().clone(),
+                Amount::from_sat(90_000),
+            ))
+            .build()
+            .unwrap();
+        let vault = ContextBuilder::new(Network::Regtest)
+            .output(Output::Tree {
+                tree: Box::new(intermediate.clone()),
+                amount: Amount::from_sat(95_000),
+                label: Some("unvault".into()),
+            })
+            .build()
+            .unwrap();
+
+        let unvault = vault.unvault_tx(Txid::all_zeros(), 0).unwrap();
+        assert!(vault.verify_spend(&unvault).unwrap());
+        let final_tx = vault.final_tx(unvault.txid()).unwrap();
+        assert!(intermediate.verify_spend(&final_tx).unwrap());
+        assert_eq!(
+            final_tx.input[0].previous_output,
+            OutPoint::new(unvault.txid(), 0)
+        );
+        assert_eq!(final_tx.input[0].sequence, Sequence::from_height(144));
+        assert_eq!(
+            vault.spending_tx(Txid::all_zeros(), 0).unwrap(),
+            [unvault, final_tx]
+        );
+
+        assert!(matches!(
+            intermediate.unvault_tx(Txid::all_zeros(), 0),
+            Err(Error::NotAVault)
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+use bitcoin::{
+    consensus::encode::serialize_hex, hashes::Hash, hex::DisplayHex, Amount, Network, Txid,
+};
+use serde_json::{json, Value};
+
+use crate::{test_util::test_payout, util::nums_point, Context, ContextBuilder, Output, TxType};
+
+/// Test vectors for the tree semantics of this crate, which the transaction-level BIP-119
+/// vectors do not cover, for other CTV libraries to check compatibility against.
+///
+/// A fixed set of contexts (a flat payment, a two-level tree, a taproot tree and an
+/// `OP_RETURN` commitment) is emitted as a JSON array. Each entry has the template, its
+/// template hash and address, and the hex of every spending transaction when funded at vout 0
+/// of the all-zero txid.
+pub fn export_test_vectors() -> String {
+    let vectors: Vec<Value> = canonical_contexts()
+        .into_iter()
+        .map(|(name, ctx)| vector(name, &ctx))
+        .collect();
+    serde_json::to_string_pretty(&vectors).expect("test vectors always serialize")
+}
+
+fn vector(name: &str, ctx: &Context) -> Value {
+    let spending_txs: Vec<String> = ctx
+        .spending_tx(Txid::all_zeros(), 0)
+        .expect("canonical contexts are spendable")
+        .iter()
+        .map(serialize_hex)
+        .collect();
+    json!({
+        "name": name,
+        "template": ctx,
+        "ctv_hash": ctx.ctv().expect("canonical contexts hash").to_lower_hex_string(),
+        "address": ctx.address().expect("canonical contexts have addresses").to_string(),
+        "spending_txs": spending_txs,
+    })
+}
+
+fn canonical_contexts() -> Vec<(&'static str, Context)> {
+    let network = Network::Regtest;
+    let context = |tx_type: TxType, outputs: Vec<Output>| {
+        ContextBuilder::new(network)
+            .tx_type(tx_type)
+            .outputs(outputs)
+            .build()
+            .expect("canonical contexts build")
+    };
+    let tree = |tx_type: TxType| {
+        let leaf = |n| Output::Tree {
+            tree: Box::new(context(
+                tx_type,
+                vec![test_payout(n, 10_000), test_payout(n + 1, 20_000)],
+            )),
+            amount: Amount::from_sat(31_000),
+            label: None,
+        };
+        context(tx_type, vec![leaf(1), leaf(3)])
+    };
+    let taproot = TxType::Taproot {
+        internal_key: nums_point("ctvlib test vectors"),
+    };
+    vec![
+        (
+            "flat payment",
+            context(
+                TxType::Segwit,
+                vec![test_payout(1, 10_000), test_payout(2, 20_000)],
+            ),
+        ),
+        ("two-level tree", tree(TxType::Segwit)),
+        ("taproot tree", tree(taproot)),
+        (
+            "op_return",
+            context(
+                TxType::Segwit,
+                vec![
+                    Output::Data {
+                        data: "ctvlib".into(),
+                        label: None,
+                    },
+                    test_payout(1, 1_000),
+                ],
+            ),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_test_vectors() {
+        let vectors: Vec<Value> = serde_json::from_str(&export_test_vectors()).unwrap();
+        assert_eq!(vectors.len(), 4);
+        assert_eq!(vectors[1]["spending_txs"].as_array().unwrap().len(), 3);
+        for vector in &vectors {
+            let ctx: Context = serde_json::from_value(vector["template"].clone()).unwrap();
+            assert_eq!(vector["ctv_hash"], ctx.ctv().unwrap().to_lower_hex_string());
+        }
+    }
+}